@@ -0,0 +1,235 @@
+// asks the compositor which output has focus, over its own IPC socket
+// every compositor gets a FocusedOutput implementation & a line in from_env
+use log::{debug, info};
+use serde_json::Value;
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// how long to wait for the compositor to answer
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub trait FocusedOutput {
+    // name of the compositor, for logging
+    fn compositor(&self) -> &'static str;
+    // name of the output that has focus, as advertised by wl_output
+    fn focused_output(&self) -> Result<String, Box<dyn Error>>;
+}
+
+// picks the IPC of the compositor we're running under, based on the environment
+pub fn from_env() -> Option<Box<dyn FocusedOutput>> {
+    if let Some(socket) = env::var_os("SWAYSOCK").or_else(|| env::var_os("I3SOCK")) {
+        return Some(Box::new(SwayIpc {
+            socket: socket.into(),
+        }));
+    }
+    if let Some(signature) = env::var_os("HYPRLAND_INSTANCE_SIGNATURE") {
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+        return Some(Box::new(HyprlandIpc {
+            socket: hyprland_socket(runtime_dir, &signature),
+        }));
+    }
+    None
+}
+
+// the socket moved from /tmp to the runtime directory in Hyprland 0.40
+// if neither exists, connecting fails with the path of the current one in the error
+fn hyprland_socket(runtime_dir: Option<PathBuf>, signature: &OsStr) -> PathBuf {
+    let sockets: Vec<PathBuf> = [runtime_dir, Some(PathBuf::from("/tmp"))]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join("hypr").join(signature).join(".socket.sock"))
+        .collect();
+    sockets
+        .iter()
+        .find(|socket| socket.exists())
+        .unwrap_or(&sockets[0])
+        .clone()
+}
+
+// name of the focused output of the compositor we're running under
+pub fn focused_output() -> Result<String, Box<dyn Error>> {
+    let Some(ipc) = from_env() else {
+        return Err(
+            "Could not find the IPC socket of the compositor, only Sway, i3 & Hyprland are supported"
+                .into(),
+        );
+    };
+    debug!("| Asking {} for the focused output", ipc.compositor());
+    let output = ipc.focused_output()?;
+    info!("> Focused output: {}", output);
+    Ok(output)
+}
+
+fn connect(socket: &PathBuf) -> Result<UnixStream, Box<dyn Error>> {
+    let stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Could not connect to {}: {}", socket.display(), e))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+// name of the first object in a list that has "focused" set
+fn find_focused(list: &Value, name_key: &str) -> Result<String, Box<dyn Error>> {
+    list.as_array()
+        .into_iter()
+        .flatten()
+        .find(|object| object["focused"].as_bool() == Some(true))
+        .and_then(|object| object[name_key].as_str())
+        .map(str::to_string)
+        .ok_or_else(|| "The compositor did not report a focused output".into())
+}
+
+// https://man.archlinux.org/man/sway-ipc.7
+pub struct SwayIpc {
+    pub socket: PathBuf,
+}
+
+impl SwayIpc {
+    const MAGIC: &'static [u8] = b"i3-ipc";
+    const GET_WORKSPACES: u32 = 1;
+
+    // sends a message without payload & returns the payload of the reply
+    fn request(&self, message_type: u32) -> Result<Value, Box<dyn Error>> {
+        let mut stream = connect(&self.socket)?;
+        let mut message = Self::MAGIC.to_vec();
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        stream.write_all(&message)?;
+
+        // magic, payload length, message type
+        let mut header = [0; 14];
+        stream.read_exact(&mut header)?;
+        if &header[..6] != Self::MAGIC {
+            return Err("Invalid reply from the IPC socket".into());
+        }
+        let length = u32::from_ne_bytes(header[6..10].try_into()?);
+        let mut payload = vec![0; length as usize];
+        stream.read_exact(&mut payload)?;
+        Ok(serde_json::from_slice(&payload)?)
+    }
+}
+
+impl FocusedOutput for SwayIpc {
+    fn compositor(&self) -> &'static str {
+        "Sway"
+    }
+    fn focused_output(&self) -> Result<String, Box<dyn Error>> {
+        // the focused workspace is on the focused output, i3 doesn't report focus in GET_OUTPUTS
+        find_focused(&self.request(Self::GET_WORKSPACES)?, "output")
+    }
+}
+
+// https://wiki.hyprland.org/IPC/
+pub struct HyprlandIpc {
+    pub socket: PathBuf,
+}
+
+impl FocusedOutput for HyprlandIpc {
+    fn compositor(&self) -> &'static str {
+        "Hyprland"
+    }
+    fn focused_output(&self) -> Result<String, Box<dyn Error>> {
+        let mut stream = connect(&self.socket)?;
+        // j/ -> reply with JSON, the compositor closes the connection after replying
+        stream.write_all(b"j/monitors")?;
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply)?;
+        find_focused(&serde_json::from_slice(&reply)?, "name")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    // answers a single request on a fake IPC socket, returns what was sent to it
+    fn serve(
+        socket: &PathBuf,
+        request_length: usize,
+        reply: Vec<u8>,
+    ) -> thread::JoinHandle<Vec<u8>> {
+        let listener = UnixListener::bind(socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; request_length];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&reply).unwrap();
+            request
+        })
+    }
+
+    #[test]
+    fn sway_focused_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("sway-ipc.sock");
+        let payload = br#"[
+            {"num": 1, "name": "1", "focused": false, "output": "DP-1"},
+            {"num": 2, "name": "2", "focused": true, "output": "HDMI-A-1"}
+        ]"#;
+        let mut reply = b"i3-ipc".to_vec();
+        reply.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        reply.extend_from_slice(&1u32.to_ne_bytes());
+        reply.extend_from_slice(payload);
+        let server = serve(&socket, 14, reply);
+
+        let ipc = SwayIpc { socket };
+        assert_eq!(ipc.focused_output().unwrap(), "HDMI-A-1");
+        let mut request = b"i3-ipc".to_vec();
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        request.extend_from_slice(&1u32.to_ne_bytes());
+        assert_eq!(server.join().unwrap(), request);
+    }
+
+    #[test]
+    fn hyprland_focused_monitor() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join(".socket.sock");
+        let reply = br#"[
+            {"id": 0, "name": "eDP-1", "focused": false},
+            {"id": 1, "name": "DP-3", "focused": true}
+        ]"#;
+        let server = serve(&socket, 10, reply.to_vec());
+
+        let ipc = HyprlandIpc { socket };
+        assert_eq!(ipc.focused_output().unwrap(), "DP-3");
+        assert_eq!(server.join().unwrap(), b"j/monitors");
+    }
+
+    #[test]
+    fn hyprland_socket_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let signature = OsStr::new("wayfreeze-test-signature");
+        let socket = dir
+            .path()
+            .join("hypr/wayfreeze-test-signature/.socket.sock");
+        // a missing socket is still reported at its current location
+        assert_eq!(hyprland_socket(Some(dir.path().into()), signature), socket);
+        assert_eq!(
+            hyprland_socket(None, signature),
+            PathBuf::from("/tmp/hypr/wayfreeze-test-signature/.socket.sock")
+        );
+    }
+
+    #[test]
+    fn nothing_focused() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join(".socket.sock");
+        let server = serve(
+            &socket,
+            10,
+            br#"[{"name": "eDP-1", "focused": false}]"#.to_vec(),
+        );
+        assert!(HyprlandIpc { socket }.focused_output().is_err());
+        server.join().unwrap();
+        // nobody listening
+        let socket = dir.path().join("missing.sock");
+        assert!(SwayIpc { socket }.focused_output().is_err());
+    }
+}
@@ -1,21 +1,41 @@
 use clap::Parser;
-use env_logger;
 use log::{debug, error, info, trace, warn};
-use std::collections::HashMap;
+use rustix::event::{poll, PollFd, PollFlags};
+use rustix::io::Errno;
+use signal_hook::consts::{SIGCHLD, SIGUSR1, SIGUSR2};
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
+use std::fs;
 use std::hash::Hash;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::AsFd;
-use std::process::Command;
-use std::thread::sleep;
-use std::time::Duration;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 use tempfile::tempfile;
 use wayland_client::{
     protocol::{
-        wl_buffer, wl_compositor, wl_keyboard, wl_output, wl_pointer, wl_registry, wl_seat, wl_shm,
-        wl_shm_pool, wl_surface,
+        wl_buffer, wl_callback, wl_compositor, wl_keyboard, wl_output, wl_pointer, wl_region,
+        wl_registry, wl_seat, wl_shm, wl_shm_pool, wl_subcompositor, wl_subsurface, wl_surface,
     },
     Connection, Dispatch, EventQueue, Proxy, QueueHandle,
 };
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::{
+        ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+        ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+    },
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1},
+        ext_image_copy_capture_manager_v1::{self, ExtImageCopyCaptureManagerV1},
+        ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+    },
+};
 use wayland_protocols::wp::{
     fractional_scale::v1::client::{
         wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
@@ -23,9 +43,16 @@ use wayland_protocols::wp::{
     },
     viewporter::{client::wp_viewport::WpViewport, client::wp_viewporter::WpViewporter},
 };
-use wayland_protocols::xdg::xdg_output::zv1::client::{
-    zxdg_output_manager_v1::ZxdgOutputManagerV1,
-    zxdg_output_v1::{self, ZxdgOutputV1},
+use wayland_protocols::xdg::{
+    shell::client::{
+        xdg_surface::{self, XdgSurface},
+        xdg_toplevel::{self, XdgToplevel},
+        xdg_wm_base::{self, XdgWmBase},
+    },
+    xdg_output::zv1::client::{
+        zxdg_output_manager_v1::ZxdgOutputManagerV1,
+        zxdg_output_v1::{self, ZxdgOutputV1},
+    },
 };
 use wayland_protocols_wlr::{
     layer_shell::v1::client::{
@@ -39,6 +66,8 @@ use wayland_protocols_wlr::{
 };
 use xkbcommon::xkb;
 
+mod focused_output;
+
 fn vec_insert<T, V>(state_hm: &mut Option<HashMap<T, V>>, key: T, value: V)
 where
     T: Eq + Hash,
@@ -56,24 +85,654 @@ where
     }
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum CaptureBackend {
+    /// Use ext-image-copy-capture-v1 if the compositor supports it, wlr-screencopy-unstable-v1 otherwise
+    #[default]
+    Auto,
+    /// wlr-screencopy-unstable-v1
+    WlrScreencopy,
+    /// ext-image-copy-capture-v1
+    ExtImageCopyCapture,
+}
+
+fn vec_remove<T, V>(state_hm: &mut Option<HashMap<T, V>>, key: &T) -> Option<V>
+where
+    T: Eq + Hash,
+{
+    state_hm.as_mut().and_then(|hm| hm.remove(key))
+}
+
+// whether a transform turns the width of a buffer into its height
+fn swaps_axes(transform: wl_output::Transform) -> bool {
+    matches!(
+        transform,
+        wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270
+    )
+}
+
+// size of a buffer once the transform has been applied to it
+fn transformed_size(size: (i32, i32), transform: wl_output::Transform) -> (i32, i32) {
+    if swaps_axes(transform) {
+        (size.1, size.0)
+    } else {
+        size
+    }
+}
+
+// transform to set on a surface that shows a frame captured from an output with the given transform
+// a y-inverted frame is flipped vertically on top of the output transform
+fn buffer_transform(
+    output_transform: wl_output::Transform,
+    y_invert: bool,
+) -> wl_output::Transform {
+    use wl_output::Transform;
+    if !y_invert {
+        return output_transform;
+    }
+    // a vertical flip is a horizontal flip followed by a rotation of 180 degrees
+    // flips & rotations don't commute: (flip, rotation) + vertical flip = (!flip, 180 - rotation)
+    match output_transform {
+        Transform::Normal => Transform::Flipped180,
+        Transform::_90 => Transform::Flipped90,
+        Transform::_180 => Transform::Flipped,
+        Transform::_270 => Transform::Flipped270,
+        Transform::Flipped => Transform::_180,
+        Transform::Flipped90 => Transform::_90,
+        Transform::Flipped180 => Transform::Normal,
+        Transform::Flipped270 => Transform::_270,
+        _ => output_transform,
+    }
+}
+
+// maps a rectangle in output-local logical coordinates onto the pixels of a frame captured from that output
+// the frame holds the output contents with the output transform applied: flipped first, then rotated
+fn logical_to_buffer(
+    region: Region,
+    transform: wl_output::Transform,
+    logical_size: (i32, i32),
+    buffer_size: (i32, i32),
+) -> Region {
+    use wl_output::Transform;
+    let (flipped, rotations) = match transform {
+        Transform::_90 => (false, 1),
+        Transform::_180 => (false, 2),
+        Transform::_270 => (false, 3),
+        Transform::Flipped => (true, 0),
+        Transform::Flipped90 => (true, 1),
+        Transform::Flipped180 => (true, 2),
+        Transform::Flipped270 => (true, 3),
+        _ => (false, 0),
+    };
+    // size of the frame before it was rotated
+    let (mut width, mut height) = match rotations % 2 {
+        1 => (buffer_size.1, buffer_size.0),
+        _ => buffer_size,
+    };
+    let scale_x = width as f64 / logical_size.0 as f64;
+    let scale_y = height as f64 / logical_size.1 as f64;
+    let x = (region.x as f64 * scale_x).floor() as i32;
+    let y = (region.y as f64 * scale_y).floor() as i32;
+    let mut rect = Region {
+        x,
+        y,
+        width: ((region.x + region.width) as f64 * scale_x).ceil() as i32 - x,
+        height: ((region.y + region.height) as f64 * scale_y).ceil() as i32 - y,
+    };
+    if flipped {
+        rect.x = width - rect.x - rect.width;
+    }
+    // every rotation turns the frame 90 degrees counter-clockwise
+    for _ in 0..rotations {
+        rect = Region {
+            x: rect.y,
+            y: width - rect.x - rect.width,
+            width: rect.height,
+            height: rect.width,
+        };
+        (width, height) = (height, width);
+    }
+    rect
+}
+
+// mode advertised by a wl_output
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OutputMode {
+    width: i32,
+    height: i32,
+    refresh: i32,
+    flags: wl_output::Mode,
+}
+
+// adds a mode to the modes of an output, or updates its flags if it was advertised before
+fn record_mode(modes: &mut Vec<OutputMode>, mode: OutputMode) {
+    // only one mode can be the current one
+    if mode.flags.contains(wl_output::Mode::Current) {
+        for m in modes.iter_mut() {
+            m.flags.remove(wl_output::Mode::Current);
+        }
+    }
+    match modes
+        .iter_mut()
+        .find(|m| (m.width, m.height, m.refresh) == (mode.width, mode.height, mode.refresh))
+    {
+        Some(m) => m.flags = mode.flags,
+        None => modes.push(mode),
+    }
+}
+
+// the mode an output is using: the current one, the preferred one if no mode is flagged as current
+fn current_mode(modes: &[OutputMode]) -> Option<OutputMode> {
+    let flagged = |flag| modes.iter().find(|m| m.flags.contains(flag)).copied();
+    flagged(wl_output::Mode::Current)
+        .or_else(|| flagged(wl_output::Mode::Preferred))
+        .or_else(|| modes.last().copied())
+}
+
+// everything known about an output & its place in the global compositor space
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+struct OutputInfo {
+    name: Option<String>,
+    description: Option<String>,
+    make: Option<String>,
+    model: Option<String>,
+    // logical position & size
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    mode: Option<ModeInfo>,
+    scale: f64,
+    transform: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+struct ModeInfo {
+    width: i32,
+    height: i32,
+    // in Hz
+    refresh: f64,
+}
+
+impl std::fmt::Display for OutputInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("(unnamed)"))?;
+        if let Some(description) = &self.description {
+            write!(f, " \"{}\"", description)?;
+        }
+        writeln!(f)?;
+        if self.make.is_some() || self.model.is_some() {
+            writeln!(
+                f,
+                "  Make & model: {} {}",
+                self.make.as_deref().unwrap_or_default(),
+                self.model.as_deref().unwrap_or_default()
+            )?;
+        }
+        // same format slurp prints, so it can be passed to --geometry
+        writeln!(
+            f,
+            "  Geometry: {},{} {}x{}",
+            self.x, self.y, self.width, self.height
+        )?;
+        if let Some(mode) = &self.mode {
+            writeln!(
+                f,
+                "  Mode: {}x{} @ {:.3} Hz",
+                mode.width, mode.height, mode.refresh
+            )?;
+        }
+        writeln!(f, "  Scale: {}", self.scale)?;
+        write!(f, "  Transform: {}", self.transform)
+    }
+}
+
+fn transform_name(transform: wl_output::Transform) -> &'static str {
+    use wl_output::Transform;
+    match transform {
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+        _ => "normal",
+    }
+}
+
+// 3x5 pixel digits, every row holds 3 bits with the leftmost pixel as the highest one
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+// argb8888 pixels that dim an output & show its number in the middle
+fn render_hint(width: i32, height: i32, number: usize) -> Vec<u8> {
+    // premultiplied & little endian -> B, G, R, A
+    let dim = [0, 0, 0, 0x99];
+    let white = [0xff, 0xff, 0xff, 0xff];
+    let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+    let mut pixels = dim.repeat(width * height);
+
+    let digits: Vec<usize> = number
+        .to_string()
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| d as usize)
+        .collect();
+    // every digit is 3 cells wide with a cell of space in between, the number is a fifth of the height
+    let cell = (height / 25).min(width / (digits.len() * 4)).max(1);
+    let text_width = (digits.len() * 4 - 1) * cell;
+    let left = width.saturating_sub(text_width) / 2;
+    let top = height.saturating_sub(5 * cell) / 2;
+    for (i, digit) in digits.iter().enumerate() {
+        for (row, bits) in DIGITS[*digit].iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                let x = left + (i * 4 + column) * cell;
+                let y = top + row * cell;
+                for py in y..(y + cell).min(height) {
+                    for px in x..(x + cell).min(width) {
+                        let offset = (py * width + px) * 4;
+                        pixels[offset..offset + 4].copy_from_slice(&white);
+                    }
+                }
+            }
+        }
+    }
+    pixels
+}
+
+// highest version of every global that wayfreeze knows how to use
+const MAX_VERSIONS: [(&str, u32); 13] = [
+    ("wl_compositor", 6),
+    ("wl_output", 4),
+    ("wl_seat", 9),
+    ("wl_shm", 2),
+    ("wl_subcompositor", 1),
+    ("wp_fractional_scale_manager_v1", 1),
+    ("wp_viewporter", 1),
+    ("zxdg_output_manager_v1", 3),
+    ("zwlr_screencopy_manager_v1", 3),
+    ("zwlr_layer_shell_v1", 4),
+    ("xdg_wm_base", 6),
+    ("ext_output_image_capture_source_manager_v1", 1),
+    ("ext_image_copy_capture_manager_v1", 1),
+];
+
+// version to bind a global at, the highest one both the compositor & wayfreeze support
+fn negotiate_version(interface: &str, version: u32) -> u32 {
+    match MAX_VERSIONS.iter().find(|(name, _)| *name == interface) {
+        Some((_, max_version)) => version.min(*max_version),
+        None => version,
+    }
+}
+
+// binds a unix socket, only replacing sockets left behind by a previous run, nobody answers on those
+fn bind_socket(path: &PathBuf) -> Result<UnixListener, Box<dyn Error>> {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        match UnixStream::connect(path) {
+            Ok(_) => return Err(format!("{} is in use by another instance", path.display()).into()),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+            Err(e) => return Err(format!("Could not check {}: {}", path.display(), e).into()),
+        }
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+// how far apart a keyboard enter & a button press can be for the press to be the one that focused the surface
+const FOCUS_CLICK_WINDOW: Duration = Duration::from_millis(200);
+
+// how long an uncovered output can take to be shown before it's captured anyway
+const UNCOVER_TIMEOUT: Duration = Duration::from_millis(500);
+
+// longest command a client of the replay socket can send, clients that go on without a newline are dropped
+const MAX_REPLAY_COMMAND_LEN: usize = 64;
+
+// reads what a client of the replay socket sent without blocking, returns the complete lines & whether it's still open
+// the partial line is kept in pending until the rest of it arrives
+fn read_socket_commands(stream: &mut UnixStream, pending: &mut Vec<u8>) -> (Vec<String>, bool) {
+    let mut buf = [0u8; MAX_REPLAY_COMMAND_LEN];
+    let open = match stream.read(&mut buf) {
+        Ok(0) => false,
+        Ok(n) => {
+            pending.extend_from_slice(&buf[..n]);
+            true
+        }
+        Err(e) => matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted),
+    };
+    let mut commands = Vec::new();
+    while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = pending.drain(..=end).collect();
+        commands.push(String::from_utf8_lossy(&line).trim().to_string());
+    }
+    if !open && !pending.is_empty() {
+        // the last command doesn't need a newline
+        commands.push(String::from_utf8_lossy(pending).trim().to_string());
+        pending.clear();
+    }
+    (commands, open && pending.len() <= MAX_REPLAY_COMMAND_LEN)
+}
+
+// wl_shm formats we can copy frames into, best first
+const SHM_FORMAT_PREFERENCE: [wl_shm::Format; 14] = [
+    wl_shm::Format::Xrgb8888,
+    wl_shm::Format::Argb8888,
+    wl_shm::Format::Xbgr8888,
+    wl_shm::Format::Abgr8888,
+    wl_shm::Format::Xrgb2101010,
+    wl_shm::Format::Xbgr2101010,
+    wl_shm::Format::Argb2101010,
+    wl_shm::Format::Abgr2101010,
+    wl_shm::Format::Xrgb16161616f,
+    wl_shm::Format::Xbgr16161616f,
+    wl_shm::Format::Argb16161616f,
+    wl_shm::Format::Abgr16161616f,
+    wl_shm::Format::Rgb565,
+    wl_shm::Format::Bgr565,
+];
+
+// amount of bytes a single pixel takes up in a buffer of the given format
+fn shm_format_bpp(format: wl_shm::Format) -> Option<u32> {
+    use wl_shm::Format;
+    match format {
+        Format::Xrgb8888
+        | Format::Argb8888
+        | Format::Xbgr8888
+        | Format::Abgr8888
+        | Format::Xrgb2101010
+        | Format::Xbgr2101010
+        | Format::Argb2101010
+        | Format::Abgr2101010 => Some(4),
+        Format::Xrgb16161616f
+        | Format::Xbgr16161616f
+        | Format::Argb16161616f
+        | Format::Abgr16161616f => Some(8),
+        Format::Rgb565 | Format::Bgr565 => Some(2),
+        _ => None,
+    }
+}
+
+// whether the compositor accepts wl_shm buffers of a format
+fn shm_format_supported(format: wl_shm::Format, supported: &[wl_shm::Format]) -> bool {
+    // argb8888 & xrgb8888 are always supported, even if they're not advertised
+    supported.contains(&format)
+        || matches!(format, wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888)
+}
+
+// picks the best format out of the ones offered for a frame that the compositor also accepts for wl_shm buffers
+fn select_shm_format(
+    offered: &[wl_shm::Format],
+    supported: &[wl_shm::Format],
+) -> Option<wl_shm::Format> {
+    SHM_FORMAT_PREFERENCE
+        .into_iter()
+        .find(|format| offered.contains(format) && shm_format_supported(*format, supported))
+}
+
+// wl_shm buffer parameters for the frame of an output
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ShmBufferInfo {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+// frame captured from an output, along with the buffer it was copied into
+#[derive(Clone, Debug)]
+struct CapturedFrame {
+    buffer: wl_buffer::WlBuffer,
+    pool: wl_shm_pool::WlShmPool,
+    info: ShmBufferInfo,
+    flags: Option<zwlr_screencopy_frame_v1::Flags>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum CaptureFailurePolicy {
+    /// Don't freeze anything
+    #[default]
+    Abort,
+    /// Freeze the other outputs, leave the failed one as it is
+    Skip,
+    /// Freeze the other outputs, cover the failed one with a solid color
+    Blank,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum FreezeLayer {
+    /// Above everything, including fullscreen windows
+    #[default]
+    Overlay,
+    /// Above windows, below overlay surfaces & usually below fullscreen windows
+    Top,
+    /// Below windows, above the background
+    Bottom,
+    /// The lowest layer, where wallpapers live
+    Background,
+}
+
+impl From<FreezeLayer> for Layer {
+    fn from(layer: FreezeLayer) -> Self {
+        match layer {
+            FreezeLayer::Overlay => Layer::Overlay,
+            FreezeLayer::Top => Layer::Top,
+            FreezeLayer::Bottom => Layer::Bottom,
+            FreezeLayer::Background => Layer::Background,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum KeyboardMode {
+    /// Grab the keyboard while frozen
+    #[default]
+    Exclusive,
+    /// Only get keyboard focus when the frozen frame is clicked
+    OnDemand,
+    /// Never take the keyboard, exit with a click, a signal or when the after-freeze command exits
+    None,
+}
+impl From<KeyboardMode> for KeyboardInteractivity {
+    fn from(mode: KeyboardMode) -> Self {
+        match mode {
+            KeyboardMode::Exclusive => KeyboardInteractivity::Exclusive,
+            KeyboardMode::OnDemand => KeyboardInteractivity::OnDemand,
+            KeyboardMode::None => KeyboardInteractivity::None,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum MirrorScaling {
+    /// Scale the frame to fit the output, with black bars around it if the aspect ratio differs
+    #[default]
+    Fit,
+    /// Scale the frame to fill the output, cutting off its edges if the aspect ratio differs
+    Fill,
+    /// Stretch the frame to the size of the output
+    Stretch,
+}
+
+// part of a frame of the given size that's shown on an output of the given size, and where on the output
+// returns the viewport source rectangle & the position & size of the mirrored frame on the output
+fn mirror_placement(
+    frame_size: (i32, i32),
+    output_size: (i32, i32),
+    scaling: MirrorScaling,
+) -> ((f64, f64, f64, f64), Region) {
+    let (frame_width, frame_height) = (frame_size.0 as f64, frame_size.1 as f64);
+    let (output_width, output_height) = (output_size.0 as f64, output_size.1 as f64);
+    let whole_frame = (0.0, 0.0, frame_width, frame_height);
+    let whole_output = Region {
+        x: 0,
+        y: 0,
+        width: output_size.0,
+        height: output_size.1,
+    };
+    let scale_x = output_width / frame_width;
+    let scale_y = output_height / frame_height;
+    match scaling {
+        MirrorScaling::Stretch => (whole_frame, whole_output),
+        MirrorScaling::Fit => {
+            let scale = scale_x.min(scale_y);
+            let width = ((frame_width * scale).round() as i32).clamp(1, output_size.0);
+            let height = ((frame_height * scale).round() as i32).clamp(1, output_size.1);
+            let placement = Region {
+                x: (output_size.0 - width) / 2,
+                y: (output_size.1 - height) / 2,
+                width,
+                height,
+            };
+            (whole_frame, placement)
+        }
+        MirrorScaling::Fill => {
+            let scale = scale_x.max(scale_y);
+            let (width, height) = (output_width / scale, output_height / scale);
+            let source = (
+                (frame_width - width) / 2.0,
+                (frame_height - height) / 2.0,
+                width,
+                height,
+            );
+            (source, whole_output)
+        }
+    }
+}
+
+// matches text against a glob pattern, * matches any amount of characters & ? a single one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last * in the pattern & the position in the text it matches up to
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // let the * match one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// rectangle in the global compositor space or in output-local logical coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Region {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Region {
+    fn intersect(&self, other: &Region) -> Option<Region> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Region {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+// parses a geometry in the format slurp prints: "x,y wxh"
+fn parse_geometry(geometry: &str) -> Result<Region, String> {
+    let invalid = || format!("invalid geometry '{geometry}', expected 'x,y wxh'");
+    let (position, size) = geometry.trim().split_once(' ').ok_or_else(invalid)?;
+    let (x, y) = position.split_once(',').ok_or_else(invalid)?;
+    let (width, height) = size.trim().split_once('x').ok_or_else(invalid)?;
+    let region = Region {
+        x: x.parse().map_err(|_| invalid())?,
+        y: y.parse().map_err(|_| invalid())?,
+        width: width.parse().map_err(|_| invalid())?,
+        height: height.parse().map_err(|_| invalid())?,
+    };
+    if region.width <= 0 || region.height <= 0 {
+        return Err(format!("geometry '{geometry}' has an empty size"));
+    }
+    Ok(region)
+}
+
 #[derive(Default)]
 struct AppData {
     compositor: Option<(wl_compositor::WlCompositor, u32)>,
-    // store all outputs in a vector
-    outputs: Option<Vec<wl_output::WlOutput>>,
-    // key is the position of the corresponding output in the above vector
+    // store all outputs, key is the order in which they were bound
+    outputs: Option<HashMap<i64, wl_output::WlOutput>>,
+    // registry names of the outputs, to clean up after outputs that are unplugged
+    output_globals: HashMap<i64, u32>,
+    bound_outputs: usize,
+    // key is the key of the corresponding output in the above map
     surfaces: Option<HashMap<i64, wl_surface::WlSurface>>,
+    xdg_outputs: Option<HashMap<i64, ZxdgOutputV1>>,
+    positions: Option<HashMap<i64, (i32, i32)>>,
     widths: Option<HashMap<i64, i32>>,
     heights: Option<HashMap<i64, i32>>,
+    // connector names & descriptions, from wl_output v4 or zxdg_output_v1
+    names: Option<HashMap<i64, String>>,
+    descriptions: Option<HashMap<i64, String>>,
+    makes: Option<HashMap<i64, String>>,
+    models: Option<HashMap<i64, String>>,
+    // integer scale advertised by wl_output, scales holds the preferred fractional scale of the surfaces
+    output_scales: Option<HashMap<i64, i32>>,
+    // integer scale preferred by the surface of an output, from wl_surface v6
+    buffer_scales: HashMap<i64, i32>,
+    // transform of the frames captured with ext-image-copy-capture
+    frame_transforms: Option<HashMap<i64, wl_output::Transform>>,
+    // part of each output that gets frozen, outputs without a region are left alone
+    regions: Option<HashMap<i64, Region>>,
+    // every mode advertised per output, the size of the current one is stored in phys_widths & phys_heights
+    modes: Option<HashMap<i64, Vec<OutputMode>>>,
     phys_widths: Option<HashMap<i64, i32>>,
     phys_heights: Option<HashMap<i64, i32>>,
     transforms: Option<HashMap<i64, wayland_client::protocol::wl_output::Transform>>,
+    screencopy_flags: Option<HashMap<i64, zwlr_screencopy_frame_v1::Flags>>,
     scales: Option<HashMap<i64, i32>>,
     viewports: Option<HashMap<i64, WpViewport>>,
+    fractional_scales: Option<HashMap<i64, WpFractionalScaleV1>>,
     shm_pools: Option<HashMap<i64, wl_shm_pool::WlShmPool>>,
+    shm_buffer_infos: Option<HashMap<i64, ShmBufferInfo>>,
     buffers: Option<HashMap<i64, wl_buffer::WlBuffer>>,
     layer_surfaces: Option<HashMap<i64, zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>>,
+    xdg_surfaces: Option<HashMap<i64, XdgSurface>>,
+    toplevels: Option<HashMap<i64, XdgToplevel>>,
     screencopy_frames: Option<HashMap<i64, ZwlrScreencopyFrameV1>>,
+    capture_sessions: Option<HashMap<i64, ExtImageCopyCaptureSessionV1>>,
+    capture_frames: Option<HashMap<i64, ExtImageCopyCaptureFrameV1>>,
+    // buffer constraints sent by an ext_image_copy_capture_session_v1
+    buffer_sizes: Option<HashMap<i64, (u32, u32)>>,
+    shm_formats: Option<HashMap<i64, Vec<wl_shm::Format>>>,
     seat: Option<(wl_seat::WlSeat, u32)>,
     pointer: Option<wl_pointer::WlPointer>,
     keyboard: Option<wl_keyboard::WlKeyboard>,
@@ -83,192 +742,1987 @@ struct AppData {
     xdg_output_manager: Option<(ZxdgOutputManagerV1, u32)>,
     fs_manager: Option<(WpFractionalScaleManagerV1, u32)>,
     viewporter: Option<(WpViewporter, u32)>,
+    subcompositor: Option<(wl_subcompositor::WlSubcompositor, u32)>,
     shm: Option<(wl_shm::WlShm, u32)>,
+    // formats advertised by wl_shm
+    shm_supported_formats: Vec<wl_shm::Format>,
     screencopy_manager: Option<(ZwlrScreencopyManagerV1, u32)>,
     layer_shell: Option<(zwlr_layer_shell_v1::ZwlrLayerShellV1, u32)>,
+    // fullscreen toplevels are used instead of layer surfaces if there's no layer-shell
+    wm_base: Option<(XdgWmBase, u32)>,
+    capture_source_manager: Option<(ExtOutputImageCaptureSourceManagerV1, u32)>,
+    copy_capture_manager: Option<(ExtImageCopyCaptureManagerV1, u32)>,
+    capture_backend: CaptureBackend,
+    capture_retries: u32,
+    capture_retry_delay: u64,
+    on_capture_failure: CaptureFailurePolicy,
+    // amount of times the capture of an output has been retried
+    capture_attempts: HashMap<i64, u32>,
+    // when the capture of an output that failed is retried, the event loop keeps running until then
+    capture_retry_deadlines: HashMap<i64, Instant>,
+    // outputs that are covered with a solid color because they couldn't be captured
+    blank_outputs: Vec<i64>,
+    // outputs of which a frame is ready to be presented
+    captured_outputs: Vec<i64>,
+    // frames that are currently attached to the surfaces
+    presented_frames: HashMap<i64, CapturedFrame>,
+    burst: u32,
+    burst_interval: u64,
+    // every frame of a burst per output, until one of them is picked
+    burst_frames: HashMap<i64, Vec<CapturedFrame>>,
+    burst_index: usize,
+    frame_picked: bool,
+    replay: Option<u64>,
+    replay_interval: u64,
+    // frames of the last few moments per output, oldest first
+    replay_frames: HashMap<i64, VecDeque<(Instant, CapturedFrame)>>,
+    // buffers that dropped out of the replay ring, reused for the next captures
+    spare_frames: HashMap<i64, Vec<CapturedFrame>>,
+    replay_requested: bool,
+    // layer & namespace of the layer surfaces, compositors can match layer rules against the namespace
+    layer: FreezeLayer,
+    namespace: String,
+    // whether the frozen surfaces take keyboard focus, without a keyboard other layer-shell tools can run on top
+    keyboard_mode: KeyboardMode,
+    // whether a frozen surface has keyboard focus, with on-demand the first click only focuses it
+    keyboard_focus: bool,
+    // when the surface got keyboard focus & when a button was last pressed on it, to tell which click focused it
+    keyboard_entered_at: Option<Instant>,
+    button_pressed_at: Option<Instant>,
+    // --passthrough: the frozen surfaces don't take any input, it goes to the windows underneath
+    passthrough: bool,
+    // --mirror: the output of which the frame is shown on all other frozen outputs
+    mirror: Option<String>,
+    mirror_scaling: MirrorScaling,
+    mirror_source: Option<i64>,
+    // surface on top of the black surface of every other output that shows the mirrored frame
+    mirror_surfaces: HashMap<
+        i64,
+        (
+            wl_surface::WlSurface,
+            wl_subsurface::WlSubsurface,
+            WpViewport,
+        ),
+    >,
+    // --pick-output: the output that was picked, the one the pointer is on & the hints shown on the others
+    pick_output: bool,
+    picked_output: Option<i64>,
+    pointer_output: Option<i64>,
+    hint_surfaces: HashMap<i64, (wl_surface::WlSurface, wl_subsurface::WlSubsurface)>,
+    hint_buffers: HashMap<i64, (wl_buffer::WlBuffer, wl_shm_pool::WlShmPool)>,
+    // --refreeze-uncover: outputs are uncovered while they're captured again, for compositors that capture our surfaces too
+    refreeze_uncover: bool,
+    // uncovered outputs & when they're captured even if the compositor never shows them uncovered, e.g. when they're off
+    uncover_deadlines: HashMap<i64, Instant>,
+    // fully transparent buffers that uncover an output while it's being captured again, by size
+    transparent_buffers: HashMap<(i32, i32), (wl_buffer::WlBuffer, wl_shm_pool::WlShmPool)>,
+    refreeze_requested: bool,
+    refreezing: bool,
+    // outputs that are being captured again, their previous frames stay up until all of them are ready
+    refreeze_outputs: Vec<i64>,
+    // frozen outputs of which the mode, scale or transform changed, these have to be captured again
+    changed_outputs: Vec<i64>,
     hide_cursor: bool,
+    geometry: Option<Region>,
+    // glob patterns that select which outputs get frozen
+    include_outputs: Vec<String>,
+    // --focused-output: the name the compositor reported, matched exactly instead of as a glob
+    focused_output: Option<String>,
+    exclude_outputs: Vec<String>,
+    freeze_on_change: bool,
+    // part of the global compositor space in which changes are watched, defaults to everything that gets frozen
+    change_geometry: Option<Region>,
+    // outputs of which a frame was captured to compare the next change against
+    change_baselines: Vec<i64>,
+    change_detected: bool,
+    // damage reported for the frame that is being captured, in buffer coordinates
+    frame_damage: HashMap<i64, Vec<Region>>,
     before_cmd: String,
     after_cmd: String,
     before_timeout: u64,
     after_timeout: u64,
+    after_child: Option<Child>,
     configured_surfaces: HashMap<i64, u32>,
+    // set once every surface shows its frame
+    frozen: bool,
+    // outputs that were plugged in while frozen, before & after they were captured
+    hotplugged_outputs: Vec<i64>,
+    pending_outputs: Vec<i64>,
     frames_ready: i32,
     outputs_ready: i32,
     output_count: i32,
     exit: bool,
 }
 
-impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
-    fn event(
-        state: &mut Self,
-        proxy: &wl_registry::WlRegistry,
-        event: <wl_registry::WlRegistry as wayland_client::Proxy>::Event,
-        _data: &(),
-        _connection: &wayland_client::Connection,
-        queue_handle: &wayland_client::QueueHandle<Self>,
-    ) {
-        match event {
-            wl_registry::Event::Global {
-                name,
-                interface,
-                version,
-            } => {
-                debug!("| Received wl_registry::Event::Global: {interface} v{version}");
-                if interface == wl_compositor::WlCompositor::interface().name
-                    && state.compositor.is_none()
-                {
-                    // wl_compositor
-                    info!("> Bound: {interface} v{version}");
-                    state.compositor = Some((proxy.bind(name, version, queue_handle, ()), name));
-                } else if interface == wl_output::WlOutput::interface().name {
-                    // wl_output
-                    info!("> Bound: {interface} v{version}");
-                    match &mut state.outputs {
-                        Some(vec) => {
-                            // this is not the first monitor
-                            vec.push(proxy.bind(name, version, queue_handle, vec.len()))
-                        }
-                        None => {
-                            // vec doesn't exist -> first monitor, index is 0
-                            let mut new_vec = Vec::new();
-                            new_vec.push(proxy.bind(name, version, queue_handle, 0));
-                            state.outputs = Some(new_vec);
-                        }
-                    }
-                } else if interface == wl_seat::WlSeat::interface().name && state.seat.is_none() {
-                    // wl_seat
-                    info!("> Bound: {interface} v{version}");
-                    let seat: wl_seat::WlSeat = proxy.bind(name, version, queue_handle, ());
-                    state.pointer = Some(seat.get_pointer(queue_handle, ()));
-                    state.keyboard = Some(seat.get_keyboard(queue_handle, ()));
-                    state.seat = Some((seat, name));
-                } else if interface == wl_shm::WlShm::interface().name && state.shm.is_none() {
-                    // wl_shm
-                    info!("> Bound: {interface} v{version}");
-                    state.shm = Some((proxy.bind(name, version, queue_handle, ()), name));
-                } else if interface == WpFractionalScaleManagerV1::interface().name
-                    && state.fs_manager.is_none()
-                {
-                    // wp_fractional_scale_manager_v1
-                    info!("> Bound: {interface} v{version}");
-                    state.fs_manager = Some((proxy.bind(name, version, queue_handle, ()), name));
-                } else if interface == WpViewporter::interface().name && state.viewporter.is_none()
-                {
-                    // wp_viewporter
-                    state.viewporter = Some((proxy.bind(name, version, queue_handle, ()), name));
-                } else if interface == ZxdgOutputManagerV1::interface().name
-                    && state.xdg_output_manager.is_none()
-                {
-                    // zxdg_output_manager_v1
-                    info!("> Bound: {interface} v{version}");
-                    state.xdg_output_manager =
-                        Some((proxy.bind(name, version, queue_handle, ()), name));
-                } else if interface == ZwlrScreencopyManagerV1::interface().name
-                    && state.screencopy_manager.is_none()
-                {
-                    // zwlr_screencopy_manager_v1
-                    info!("> Bound: {interface} v{version}");
-                    state.screencopy_manager =
-                        Some((proxy.bind(name, version, queue_handle, ()), name));
-                } else if interface == zwlr_layer_shell_v1::ZwlrLayerShellV1::interface().name
-                    && state.layer_shell.is_none()
-                {
-                    // zwlr_layer_shell_v1
-                    info!("> Bound: {interface} v{version}");
-                    state.layer_shell = Some((proxy.bind(name, version, queue_handle, ()), name));
+impl AppData {
+    // starts capturing an output with the selected capture backend, the buffer is filled in by the dispatchers
+    fn capture_output(
+        &mut self,
+        output: i64,
+        queue_handle: &QueueHandle<Self>,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(outputs) = &self.outputs else {
+            return Err("Could not load WlOutputs".into());
+        };
+        trace!("  capturing output {}", output);
+        match self.capture_backend {
+            CaptureBackend::ExtImageCopyCapture => {
+                let Some((source_manager, _)) = &self.capture_source_manager else {
+                    return Err("No ExtOutputImageCaptureSourceManagerV1 loaded".into());
+                };
+                let Some((copy_manager, _)) = &self.copy_capture_manager else {
+                    return Err("No ExtImageCopyCaptureManagerV1 loaded".into());
+                };
+                // create a capture session for the output, sends buffer constraints followed by Done
+                let source = source_manager.create_source(&outputs[&output], queue_handle, ());
+                let options = if self.hide_cursor {
+                    ext_image_copy_capture_manager_v1::Options::empty()
+                } else {
+                    ext_image_copy_capture_manager_v1::Options::PaintCursors
                 };
+                let session = copy_manager.create_session(&source, options, queue_handle, output);
+                // the session keeps capturing the output, the source itself is no longer needed
+                source.destroy();
+                vec_insert(&mut self.capture_sessions, output, session);
             }
-            wl_registry::Event::GlobalRemove { name } => {
-                debug!("| Received wl_registry::Event::GlobalRemove");
-                if let Some((_, compositor_name)) = &state.compositor {
-                    if name == *compositor_name {
-                        warn!("WlCompositor was removed");
-                        state.compositor = None;
-                    }
-                } else if let Some((_, seat_name)) = &state.seat {
-                    if name == *seat_name {
-                        warn!("WlSeat was removed");
-                        state.seat = None;
-                    }
-                } else if let Some((_, shm_name)) = &state.shm {
-                    if name == *shm_name {
-                        warn!("WlShm was removed");
-                        state.shm = None;
-                    }
-                } else if let Some((_, fsmanager_name)) = &state.fs_manager {
-                    if name == *fsmanager_name {
-                        warn!("WpFractionalScaleManagerV1 was removed");
-                        state.fs_manager = None;
-                    }
-                } else if let Some((_, viewporter_name)) = &state.viewporter {
-                    if name == *viewporter_name {
-                        warn!("WpViewporter was removed");
-                        state.viewporter = None;
-                    }
-                } else if let Some((_, screencopymanager_name)) = &state.screencopy_manager {
-                    if name == *screencopymanager_name {
-                        warn!("ZwlrScreencopyManagerV1 was removed");
-                        state.screencopy_manager = None;
-                    }
-                } else if let Some((_, layer_shell_name)) = &state.layer_shell {
-                    if name == *layer_shell_name {
-                        warn!("ZwlrLayerShellV1 was removed");
-                        state.layer_shell = None;
+            _ => {
+                let Some((screencopy_manager, _)) = &self.screencopy_manager else {
+                    return Err("No ZwlrScreencopyManagerV1 loaded".into());
+                };
+                // create screencopyframe from output
+                let screencopy_frame = match (self.geometry, &self.regions) {
+                    (Some(_), Some(regions)) => {
+                        let region = regions[&output];
+                        screencopy_manager.capture_output_region(
+                            !self.hide_cursor as i32,
+                            &outputs[&output],
+                            region.x,
+                            region.y,
+                            region.width,
+                            region.height,
+                            queue_handle,
+                            output,
+                        )
                     }
-                }
+                    _ => screencopy_manager.capture_output(
+                        !self.hide_cursor as i32,
+                        &outputs[&output],
+                        queue_handle,
+                        output,
+                    ),
+                };
+                vec_insert(&mut self.screencopy_frames, output, screencopy_frame);
             }
-            _ => {}
         }
+        Ok(())
     }
-}
-
-impl Dispatch<wl_output::WlOutput, usize> for AppData {
-    fn event(
-        state: &mut Self,
-        proxy: &wl_output::WlOutput,
-        event: <wl_output::WlOutput as Proxy>::Event,
-        data: &usize,
-        _connection: &wayland_client::Connection,
-        queue_handle: &wayland_client::QueueHandle<Self>,
-    ) {
-        match event {
-            wl_output::Event::Mode {
-                flags: _,
-                width,
-                height,
-                refresh: _,
-            } => {
-                debug!("| Received wl_output::Event::Mode for output {}", data);
-                // describes an available output mode for the output
-                // save the physical width & height of this output under the same key as this output's index in the vector
-                vec_insert(&mut state.phys_widths, *data as i64, width);
-                vec_insert(&mut state.phys_heights, *data as i64, height);
+    // destroys everything a previous capture of an output left behind
+    fn reset_capture(&mut self, output: i64) {
+        if let Some(frame) = self
+            .screencopy_frames
+            .as_mut()
+            .and_then(|f| f.remove(&output))
+        {
+            frame.destroy();
+        }
+        if let Some(frame) = self.capture_frames.as_mut().and_then(|f| f.remove(&output)) {
+            frame.destroy();
+        }
+        if let Some(session) = self
+            .capture_sessions
+            .as_mut()
+            .and_then(|s| s.remove(&output))
+        {
+            session.destroy();
+        }
+        // the presented frame stays attached until a new one replaces it
+        let presented = self.presented_frames.get(&output);
+        if let Some(buffer) = self.buffers.as_mut().and_then(|b| b.remove(&output)) {
+            if presented.is_none_or(|frame| frame.buffer != buffer) {
+                buffer.destroy();
             }
-            wl_output::Event::Geometry {
-                x: _,
-                y: _,
-                physical_width: _,
-                physical_height: _,
-                subpixel: _,
-                make: _,
-                model: _,
-                transform,
-            } => {
-                debug!("| Received wl_output::Event::Geometry for output {}", data);
-                // describes transformations that clients and compositors apply to buffer contents
-
-                vec_insert(
-                    &mut state.transforms,
-                    *data as i64,
-                    transform.into_result().unwrap(),
-                );
+        }
+        if let Some(pool) = self.shm_pools.as_mut().and_then(|p| p.remove(&output)) {
+            if presented.is_none_or(|frame| frame.pool != pool) {
+                pool.destroy();
+            }
+        }
+        if let Some(formats) = &mut self.shm_formats {
+            formats.remove(&output);
+        }
+        self.frame_damage.remove(&output);
+        if let Some(sizes) = &mut self.buffer_sizes {
+            sizes.remove(&output);
+        }
+        if let Some(infos) = &mut self.shm_buffer_infos {
+            infos.remove(&output);
+        }
+        if let Some(flags) = &mut self.screencopy_flags {
+            flags.remove(&output);
+        }
+    }
+    // retries a failed capture with exponential backoff, applies the failure policy once out of retries
+    fn capture_failed(&mut self, output: i64, queue_handle: &QueueHandle<Self>) {
+        self.reset_capture(output);
 
-                let Some((xdg_output_manager, _)) = &state.xdg_output_manager else {
-                    error!("No ZxdgOutputManagerV1 loaded");
+        let attempts = self.capture_attempts.entry(output).or_insert(0);
+        if *attempts < self.capture_retries {
+            *attempts += 1;
+            let delay = self
+                .capture_retry_delay
+                .saturating_mul(2u64.saturating_pow(*attempts - 1));
+            warn!(
+                "Failed to capture output {}, retrying in {}ms ({}/{})",
+                output, delay, attempts, self.capture_retries
+            );
+            self.capture_retry_deadlines
+                .insert(output, Instant::now() + Duration::from_millis(delay));
+            return;
+        }
+
+        if self.refreezing && self.refreeze_outputs.contains(&output) {
+            warn!(
+                "Failed to capture output {} again, keeping the previous frame",
+                output
+            );
+            self.captured_outputs.push(output);
+            self.frames_ready += 1;
+            return;
+        }
+        if self.frozen {
+            // an output that was plugged in while frozen, the rest of the screen stays frozen either way
+            warn!("Failed to capture output {}, leaving it unfrozen", output);
+            vec_remove(&mut self.regions, &output);
+            return;
+        }
+
+        match self.on_capture_failure {
+            CaptureFailurePolicy::Abort => {
+                error!("Failed to capture output {} - exiting...", output);
+                self.exit = true;
+            }
+            CaptureFailurePolicy::Skip => {
+                warn!("Failed to capture output {}, leaving it unfrozen", output);
+                if let Some(regions) = &mut self.regions {
+                    regions.remove(&output);
+                }
+            }
+            CaptureFailurePolicy::Blank => {
+                warn!("Failed to capture output {}, covering it instead", output);
+                if !self.cover_output(output, queue_handle) {
+                    self.exit = true;
+                }
+            }
+        }
+    }
+    // size of a buffer filled with a single color, the viewport can stretch a single pixel over the whole region
+    fn pixel_buffer_size(&self, output: i64) -> (i32, i32) {
+        if self.viewporter.is_some() {
+            return (1, 1);
+        }
+        match &self.regions {
+            Some(regions) => regions
+                .get(&output)
+                .map_or((1, 1), |r| (r.width.max(1), r.height.max(1))),
+            None => (1, 1),
+        }
+    }
+    // creates a buffer filled with a single color
+    fn create_pixel_buffer(
+        &self,
+        format: wl_shm::Format,
+        pixel: [u8; 4],
+        (width, height): (i32, i32),
+        queue_handle: &QueueHandle<Self>,
+    ) -> Option<(wl_buffer::WlBuffer, wl_shm_pool::WlShmPool)> {
+        let Some((shm, _)) = &self.shm else {
+            error!("No WlShm loaded");
+            return None;
+        };
+        let mut tmp = tempfile().expect("Unable to create tempfile");
+        tmp.write_all(&pixel.repeat((width * height) as usize))
+            .unwrap();
+        let pool: wl_shm_pool::WlShmPool =
+            shm.create_pool(tmp.as_fd(), width * height * 4, queue_handle, ());
+        let buffer: wl_buffer::WlBuffer =
+            pool.create_buffer(0, width, height, width * 4, format, queue_handle, ());
+        Some((buffer, pool))
+    }
+    // creates a buffer for a wlr-screencopy frame once its parameters are known & copies the frame into it
+    fn copy_screencopy_frame(
+        &mut self,
+        output: i64,
+        frame: &ZwlrScreencopyFrameV1,
+        queue_handle: &QueueHandle<Self>,
+    ) {
+        let Some(info) = self
+            .shm_buffer_infos
+            .as_ref()
+            .and_then(|infos| infos.get(&output).copied())
+        else {
+            error!("No shm buffer parameters received for output {}", output);
+            self.capture_failed(output, queue_handle);
+            return;
+        };
+        // screencopy only offers a single shm format & the stride that goes with it, the compositor only has to accept it
+        if !shm_format_supported(info.format, &self.shm_supported_formats) {
+            error!(
+                "Shm format {:?} of output {} can't be presented",
+                info.format, output
+            );
+            self.capture_failed(output, queue_handle);
+            return;
+        }
+        let Some(buffer) = self.create_shm_buffer(output, info, queue_handle) else {
+            self.capture_failed(output, queue_handle);
+            return;
+        };
+        trace!("  copying frame to buffer");
+        // copy frame to buffer, sends Ready when successful
+        if self.freeze_on_change && !self.change_detected && self.change_baselines.contains(&output)
+        {
+            // the baseline was copied right away, this waits until the output changes & sends Damage before Ready
+            frame.copy_with_damage(&buffer);
+        } else {
+            frame.copy(&buffer);
+        }
+    }
+    // captures the outputs of which the retry delay has passed
+    fn retry_captures(&mut self, queue_handle: &QueueHandle<Self>) {
+        let now = Instant::now();
+        let mut due: Vec<i64> = self
+            .capture_retry_deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(output, _)| *output)
+            .collect();
+        due.sort();
+        for output in due {
+            self.capture_retry_deadlines.remove(&output);
+            trace!("  retrying capture of output {}", output);
+            if let Err(e) = self.capture_output(output, queue_handle) {
+                error!("Could not capture output {}: {}", output, e);
+                self.capture_failed(output, queue_handle);
+            }
+        }
+    }
+    // covers an output with black instead of capturing it
+    fn cover_output(&mut self, output: i64, queue_handle: &QueueHandle<Self>) -> bool {
+        if self.create_blank_buffer(output, queue_handle).is_none() {
+            return false;
+        }
+        self.blank_outputs.push(output);
+        self.captured_outputs.push(output);
+        self.frames_ready += 1;
+        true
+    }
+    // creates a single black pixel buffer to cover an output with
+    fn create_blank_buffer(
+        &mut self,
+        output: i64,
+        queue_handle: &QueueHandle<Self>,
+    ) -> Option<wl_buffer::WlBuffer> {
+        // xrgb8888 is little endian -> B, G, R, X
+        let (width, height) = self.pixel_buffer_size(output);
+        let (buffer, pool) = self.create_pixel_buffer(
+            wl_shm::Format::Xrgb8888,
+            [0, 0, 0, 255],
+            (width, height),
+            queue_handle,
+        )?;
+        let info = ShmBufferInfo {
+            format: wl_shm::Format::Xrgb8888,
+            width: width as u32,
+            height: height as u32,
+            stride: width as u32 * 4,
+        };
+        vec_insert(&mut self.shm_pools, output, pool);
+        vec_insert(&mut self.shm_buffer_infos, output, info);
+        vec_insert(&mut self.buffers, output, buffer.clone());
+        Some(buffer)
+    }
+    // allocates a pool that fits a buffer with the given parameters & creates the buffer in it
+    fn create_shm_buffer(
+        &mut self,
+        output: i64,
+        info: ShmBufferInfo,
+        queue_handle: &QueueHandle<Self>,
+    ) -> Option<wl_buffer::WlBuffer> {
+        if let Some(spares) = self.spare_frames.get_mut(&output) {
+            // reuse a buffer with the same parameters, the others won't fit anymore
+            let spare = spares
+                .iter()
+                .position(|frame| frame.info == info)
+                .map(|index| spares.swap_remove(index));
+            if spare.is_none() {
+                for frame in spares.drain(..) {
+                    frame.buffer.destroy();
+                    frame.pool.destroy();
+                }
+            }
+            if let Some(frame) = spare {
+                trace!("  reusing buffer of output {}", output);
+                vec_insert(&mut self.shm_pools, output, frame.pool);
+                vec_insert(&mut self.shm_buffer_infos, output, info);
+                vec_insert(&mut self.buffers, output, frame.buffer.clone());
+                return Some(frame.buffer);
+            }
+        }
+        let Some((shm, _)) = &self.shm else {
+            error!("No WlShm loaded");
+            return None;
+        };
+        if let (Some(phys_widths), Some(phys_heights)) = (&self.phys_widths, &self.phys_heights) {
+            if self.geometry.is_none()
+                && (phys_widths.get(&output), phys_heights.get(&output))
+                    != (Some(&(info.width as i32)), Some(&(info.height as i32)))
+            {
+                debug!(
+                    "| Buffer size {}x{} of output {} differs from its mode",
+                    info.width, info.height, output
+                );
+            }
+        }
+        // create pool, stride * height -> total size of the buffer
+        let pool_size = info.stride as usize * info.height as usize;
+        let Ok(pool_size_i32) = i32::try_from(pool_size) else {
+            error!(
+                "Buffer for output {} is too large: {} bytes",
+                output, pool_size
+            );
+            return None;
+        };
+        let tmp = tempfile().expect("Unable to create tempfile");
+        tmp.set_len(pool_size as u64).unwrap();
+        let pool: wl_shm_pool::WlShmPool =
+            shm.create_pool(tmp.as_fd(), pool_size_i32, queue_handle, ());
+
+        trace!(
+            "  creating buffer, width: {}, height: {}, stride: {}, format: {:?}",
+            info.width,
+            info.height,
+            info.stride,
+            info.format
+        );
+        let buffer: wl_buffer::WlBuffer = pool.create_buffer(
+            0, // buffer takes up the whole pool -> offset 0
+            info.width as i32,
+            info.height as i32,
+            info.stride as i32,
+            info.format,
+            queue_handle,
+            (),
+        );
+        vec_insert(&mut self.shm_pools, output, pool);
+        vec_insert(&mut self.shm_buffer_infos, output, info);
+        vec_insert(&mut self.buffers, output, buffer.clone());
+        Some(buffer)
+    }
+    // source rectangle for the viewport of an output, in the surface coordinates of its buffer
+    fn viewport_source(&self, output: i64) -> (f64, f64, f64, f64) {
+        let whole_buffer = (-1.0, -1.0, -1.0, -1.0);
+        // wlr-screencopy captures the region itself, ext-image-copy-capture always copies the whole output
+        if self.geometry.is_none()
+            || self.capture_backend != CaptureBackend::ExtImageCopyCapture
+            || self.blank_outputs.contains(&output)
+        {
+            return whole_buffer;
+        }
+        let (Some(regions), Some(buffer_infos), Some(widths), Some(heights)) = (
+            &self.regions,
+            &self.shm_buffer_infos,
+            &self.widths,
+            &self.heights,
+        ) else {
+            return whole_buffer;
+        };
+        let region = regions[&output];
+        let info = buffer_infos[&output];
+        let (buffer_width, buffer_height) = transformed_size(
+            (info.width as i32, info.height as i32),
+            self.content_transform(output),
+        );
+        let scale_x = buffer_width as f64 / widths[&output] as f64;
+        let scale_y = buffer_height as f64 / heights[&output] as f64;
+        (
+            region.x as f64 * scale_x,
+            region.y as f64 * scale_y,
+            region.width as f64 * scale_x,
+            region.height as f64 * scale_y,
+        )
+    }
+    // part of the frame of an output in which changes are watched, in buffer coordinates
+    fn watched_area(&self, output: i64) -> Option<Region> {
+        let (Some(regions), Some(buffer_infos), Some(widths), Some(heights)) = (
+            &self.regions,
+            &self.shm_buffer_infos,
+            &self.widths,
+            &self.heights,
+        ) else {
+            return None;
+        };
+        let region = *regions.get(&output)?;
+        let mut watched = match self.change_geometry {
+            Some(change_geometry) => {
+                let (x, y) = match &self.positions {
+                    Some(positions) => positions.get(&output).copied().unwrap_or_default(),
+                    None => (0, 0),
+                };
+                let area = change_geometry.intersect(&Region {
+                    x,
+                    y,
+                    width: widths[&output],
+                    height: heights[&output],
+                })?;
+                Region {
+                    x: area.x - x,
+                    y: area.y - y,
+                    ..area
+                }
+                .intersect(&region)?
+            }
+            None => region,
+        };
+        // wlr-screencopy only copies the region itself
+        let mut logical_size = (widths[&output], heights[&output]);
+        if self.geometry.is_some() && self.capture_backend != CaptureBackend::ExtImageCopyCapture {
+            watched.x -= region.x;
+            watched.y -= region.y;
+            logical_size = (region.width, region.height);
+        }
+        let info = buffer_infos.get(&output)?;
+        Some(logical_to_buffer(
+            watched,
+            self.content_transform(output),
+            logical_size,
+            (info.width as i32, info.height as i32),
+        ))
+    }
+    // decides whether a captured frame gets presented when freezing on the next change
+    fn change_frame_ready(&mut self, output: i64, queue_handle: &QueueHandle<Self>) -> bool {
+        if !self.freeze_on_change || self.change_detected {
+            return true;
+        }
+        let damage = self.frame_damage.remove(&output).unwrap_or_default();
+        if !self.change_baselines.contains(&output) {
+            // the first frame is copied without waiting for damage, it shows what's on the screen right now
+            trace!("  captured output {}, waiting for changes", output);
+            self.change_baselines.push(output);
+        } else if let Some(watched) = self.watched_area(output) {
+            if damage.iter().any(|d| d.intersect(&watched).is_some()) {
+                info!("> Output {} changed", output);
+                self.change_detected = true;
+                // capture the other outputs as they are right now
+                let others: Vec<i64> = match &self.regions {
+                    Some(regions) => regions.keys().copied().filter(|o| *o != output).collect(),
+                    None => Vec::new(),
+                };
+                for other in others {
+                    self.reset_capture(other);
+                    if let Err(e) = self.capture_output(other, queue_handle) {
+                        error!("Could not capture output {}: {}", other, e);
+                        self.capture_failed(other, queue_handle);
+                    }
+                }
+                return true;
+            }
+            trace!("  output {} changed outside of the watched area", output);
+        }
+        self.capture_next_change(output, queue_handle);
+        false
+    }
+    // captures the next frame of an output, the compositor only copies it once the output has changed
+    fn capture_next_change(&mut self, output: i64, queue_handle: &QueueHandle<Self>) {
+        if self.capture_backend != CaptureBackend::ExtImageCopyCapture {
+            // the frame is copied with copy_with_damage, see copy_screencopy_frame
+            self.reset_capture(output);
+            if let Err(e) = self.capture_output(output, queue_handle) {
+                error!("Could not capture output {}: {}", output, e);
+                self.capture_failed(output, queue_handle);
+            }
+            return;
+        }
+        // only the first frame of a session is copied right away, reuse the session & its buffer
+        let (Some(sessions), Some(buffers), Some(buffer_infos)) = (
+            &self.capture_sessions,
+            &self.buffers,
+            &self.shm_buffer_infos,
+        ) else {
+            self.capture_failed(output, queue_handle);
+            return;
+        };
+        let info = buffer_infos[&output];
+        let frame = sessions[&output].create_frame(queue_handle, output);
+        frame.attach_buffer(&buffers[&output]);
+        frame.damage_buffer(0, 0, info.width as i32, info.height as i32);
+        frame.capture();
+        if let Some(previous) = self
+            .capture_frames
+            .as_mut()
+            .and_then(|f| f.insert(output, frame))
+        {
+            previous.destroy();
+        }
+    }
+    // scale to present the frame of an output at, the viewport takes care of scaling if there is one
+    fn buffer_scale(&self, output: i64) -> i32 {
+        if self.viewporter.is_some() {
+            return 1;
+        }
+        let (Some(regions), Some(infos)) = (&self.regions, &self.shm_buffer_infos) else {
+            return 1;
+        };
+        let (Some(region), Some(info)) = (regions.get(&output), infos.get(&output)) else {
+            return 1;
+        };
+        // wl_surface v6 tells us which scale it prefers, otherwise use the one of the output
+        let scale = match self.buffer_scales.get(&output) {
+            Some(scale) => *scale,
+            None => match &self.output_scales {
+                Some(scales) => scales.get(&output).copied().unwrap_or(1),
+                None => 1,
+            },
+        };
+        let (width, height) = transformed_size(
+            (info.width as i32, info.height as i32),
+            self.surface_transform(output),
+        );
+        if scale > 1 && (width, height) == (region.width * scale, region.height * scale) {
+            scale
+        } else {
+            1
+        }
+    }
+    // transform to set on the surface of an output, so that its frame matches the live screen
+    fn surface_transform(&self, output: i64) -> wl_output::Transform {
+        // blank buffers aren't captured, they already have the size of the surface
+        if self.blank_outputs.contains(&output) {
+            return wl_output::Transform::Normal;
+        }
+        let y_invert = match &self.screencopy_flags {
+            Some(flags) => flags
+                .get(&output)
+                .is_some_and(|flags| flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert)),
+            None => false,
+        };
+        buffer_transform(self.content_transform(output), y_invert)
+    }
+    // transform that turns a frame captured from an output into what's shown on it
+    fn content_transform(&self, output: i64) -> wl_output::Transform {
+        // ext-image-copy-capture tells us per frame, wlr-screencopy frames have the transform of the output
+        let frame_transform = self
+            .frame_transforms
+            .as_ref()
+            .and_then(|t| t.get(&output).copied());
+        let output_transform = self
+            .transforms
+            .as_ref()
+            .and_then(|t| t.get(&output).copied());
+        frame_transform
+            .or(output_transform)
+            .unwrap_or(wl_output::Transform::Normal)
+    }
+    // whether a captured frame has the size of the part of the output it was captured from
+    fn frame_size_matches(&self, output: i64) -> bool {
+        let (Some(infos), Some(widths), Some(heights)) =
+            (&self.shm_buffer_infos, &self.widths, &self.heights)
+        else {
+            return true;
+        };
+        let (Some(info), Some(&width), Some(&height)) = (
+            infos.get(&output),
+            widths.get(&output),
+            heights.get(&output),
+        ) else {
+            return true;
+        };
+        // wlr-screencopy only copies the region itself
+        let (width, height) = match (&self.regions, self.geometry) {
+            (Some(regions), Some(_))
+                if self.capture_backend != CaptureBackend::ExtImageCopyCapture =>
+            {
+                regions
+                    .get(&output)
+                    .map_or((width, height), |r| (r.width, r.height))
+            }
+            _ => (width, height),
+        };
+        let (buffer_width, buffer_height) = transformed_size(
+            (info.width as i32, info.height as i32),
+            self.content_transform(output),
+        );
+        // the scale may be fractional, so only compare the aspect ratios, rounded to a pixel
+        let expected_height = buffer_width as f64 * height as f64 / width as f64;
+        (expected_height - buffer_height as f64).abs()
+            <= (buffer_width as f64 / width as f64).max(1.0)
+    }
+    // warns about frames that would end up stretched, e.g. when the transform of the output isn't known yet
+    fn check_frame_size(&self, output: i64) {
+        if !self.frame_size_matches(output) {
+            warn!(
+                "Frame of output {} doesn't match its size with transform {}",
+                output,
+                transform_name(self.content_transform(output))
+            );
+        }
+    }
+    // attaches the captured frame of an output to its surface, replaces the frame that was presented before
+    fn present_frame(&mut self, output: i64) {
+        self.present_frames(&[output]);
+    }
+    // attaches the frames of all outputs first & commits their surfaces together, so they're swapped in at once
+    fn present_frames(&mut self, outputs: &[i64]) {
+        let mut presented = Vec::new();
+        for output in outputs {
+            if let Some(previous) = self.attach_frame(*output) {
+                presented.push((*output, previous));
+            }
+        }
+        if let Some(surfaces) = &self.surfaces {
+            for (output, _) in &presented {
+                surfaces[output].commit();
+            }
+        }
+
+        for (output, previous) in presented {
+            // mirrors get the new frame before the previous one is destroyed
+            if self.is_mirror_target(output) {
+                self.present_mirror(output);
+            } else if self.mirror_source == Some(output) {
+                let mut targets: Vec<i64> = self.mirror_surfaces.keys().copied().collect();
+                targets.sort();
+                for target in targets {
+                    self.present_mirror(target);
+                }
+            }
+
+            if let Some(previous) = previous {
+                // frames of a burst are kept around until one of them is picked
+                if previous.buffer != self.presented_frames[&output].buffer
+                    && !self
+                        .burst_frames
+                        .values()
+                        .flatten()
+                        .any(|f| f.buffer == previous.buffer)
+                {
+                    previous.buffer.destroy();
+                    previous.pool.destroy();
+                }
+            }
+        }
+    }
+    // attaches the frame of an output without committing, returns the frame it replaces if it has one loaded
+    fn attach_frame(&mut self, output: i64) -> Option<Option<CapturedFrame>> {
+        let (Some(surfaces), Some(buffers), Some(pools), Some(infos)) = (
+            &self.surfaces,
+            &self.buffers,
+            &self.shm_pools,
+            &self.shm_buffer_infos,
+        ) else {
+            error!("No frame loaded for output {}", output);
+            return None;
+        };
+        // e.g. the output is being captured again
+        let (Some(surface), Some(buffer), Some(pool), Some(info)) = (
+            surfaces.get(&output),
+            buffers.get(&output),
+            pools.get(&output),
+            infos.get(&output),
+        ) else {
+            debug!("| No frame loaded for output {}, not presenting", output);
+            return None;
+        };
+        let frame = CapturedFrame {
+            buffer: buffer.clone(),
+            pool: pool.clone(),
+            info: *info,
+            flags: self
+                .screencopy_flags
+                .as_ref()
+                .and_then(|flags| flags.get(&output).copied()),
+        };
+
+        trace!("  attaching buffer to surface {}", output);
+        surface.attach(Some(&frame.buffer), 0, 0);
+        surface.set_buffer_scale(self.buffer_scale(output));
+        surface.set_buffer_transform(self.surface_transform(output));
+        surface.damage(0, 0, i32::MAX, i32::MAX);
+        if let Some(viewports) = &self.viewports {
+            let (x, y, width, height) = self.viewport_source(output);
+            viewports[&output].set_source(x, y, width, height);
+        }
+
+        Some(self.presented_frames.insert(output, frame))
+    }
+    // presents the frame of an output once its surface has been configured, or again after it was reconfigured
+    fn surface_configured(&mut self, output: i64, serial: u32) {
+        if let Some(configured) = self.configured_surfaces.get_mut(&output) {
+            // e.g. the output changed, the acknowledgement has to be committed
+            trace!("  surface {} reconfigured", output);
+            *configured = serial;
+            if self.refreeze_outputs.contains(&output) {
+                // the new frame gets presented once it's captured
+                if let Some(surface) = self.surfaces.as_ref().and_then(|s| s.get(&output)) {
+                    surface.commit();
+                }
+            } else {
+                self.present_frame(output);
+            }
+            return;
+        }
+
+        let Some(surfaces) = &self.surfaces else {
+            error!("No WlSurface loaded");
+            return;
+        };
+        trace!(
+            "  committing to surface {} before attaching buffers",
+            output
+        );
+        surfaces[&output].commit(); // commit before attaching any buffers
+
+        self.present_frame(output);
+
+        self.configured_surfaces.insert(output, serial);
+    }
+    // whether an output shows the frame of the --mirror output
+    fn is_mirror_target(&self, output: i64) -> bool {
+        self.mirror_source.is_some_and(|source| source != output)
+    }
+    // shows the frame of the --mirror output on top of the black surface of another output
+    fn present_mirror(&self, output: i64) {
+        let (Some(source), Some((surface, subsurface, viewport))) =
+            (self.mirror_source, self.mirror_surfaces.get(&output))
+        else {
+            return;
+        };
+        let (Some(frame), Some(region), Some(parent)) = (
+            self.presented_frames.get(&source),
+            self.regions.as_ref().and_then(|r| r.get(&output)),
+            self.surfaces.as_ref().and_then(|s| s.get(&output)),
+        ) else {
+            return;
+        };
+        let transform = self.surface_transform(source);
+        let frame_size = transformed_size(
+            (frame.info.width as i32, frame.info.height as i32),
+            transform,
+        );
+        let ((x, y, width, height), placement) = mirror_placement(
+            frame_size,
+            (region.width, region.height),
+            self.mirror_scaling,
+        );
+        trace!(
+            "  mirroring output {} onto output {} at {:?}",
+            source,
+            output,
+            placement
+        );
+        surface.attach(Some(&frame.buffer), 0, 0);
+        surface.set_buffer_transform(transform);
+        viewport.set_source(x, y, width, height);
+        viewport.set_destination(placement.width, placement.height);
+        surface.damage(0, 0, i32::MAX, i32::MAX);
+        surface.commit();
+        // the position of a subsurface is applied with the next commit of its parent
+        subsurface.set_position(placement.x, placement.y);
+        parent.commit();
+    }
+    // captures every frozen output again, the new frames are presented once all of them are ready
+    fn refreeze(&mut self, queue_handle: &QueueHandle<Self>) {
+        if self.refreezing {
+            debug!("| Already refreezing, ignoring");
+            return;
+        }
+        if !self.burst_frames.is_empty() {
+            info!("> Pick a frame of the burst before refreezing");
+            return;
+        }
+        // outputs that mirror another one follow along with it
+        let mut outputs: Vec<i64> = self
+            .presented_frames
+            .keys()
+            .copied()
+            .filter(|o| !self.is_mirror_target(*o))
+            .collect();
+        outputs.sort();
+        info!("> Refreezing {} output(s)", outputs.len());
+        self.refreeze_outputs(outputs, queue_handle);
+    }
+    // captures some of the frozen outputs again, with --refreeze-uncover they are uncovered first
+    fn refreeze_outputs(&mut self, outputs: Vec<i64>, queue_handle: &QueueHandle<Self>) {
+        self.refreezing = true;
+        self.frames_ready = 0;
+        self.captured_outputs.retain(|o| !outputs.contains(o));
+        self.capture_attempts.retain(|o, _| !outputs.contains(o));
+        self.capture_retry_deadlines
+            .retain(|o, _| !outputs.contains(o));
+        self.uncover_deadlines.retain(|o, _| !outputs.contains(o));
+        self.refreeze_outputs = outputs.clone();
+
+        if !self.refreeze_uncover {
+            // the frozen frames stay attached, the new ones are captured into buffers of their own
+            for output in outputs {
+                self.reset_capture(output);
+                if let Err(e) = self.capture_output(output, queue_handle) {
+                    error!("Could not capture output {}: {}", output, e);
+                    self.capture_failed(output, queue_handle);
+                }
+            }
+            return;
+        }
+        // compositors that include our own surfaces in a capture would capture the old frame again
+        debug!(
+            "| Uncovering {} output(s) while they're captured again",
+            outputs.len()
+        );
+        for output in outputs {
+            self.reset_capture(output);
+            let size = self.pixel_buffer_size(output);
+            if !self.transparent_buffers.contains_key(&size) {
+                if let Some(transparent) = self.create_pixel_buffer(
+                    wl_shm::Format::Argb8888,
+                    [0, 0, 0, 0],
+                    size,
+                    queue_handle,
+                ) {
+                    self.transparent_buffers.insert(size, transparent);
+                }
+            }
+            let (Some(surfaces), Some((buffer, _))) =
+                (&self.surfaces, self.transparent_buffers.get(&size))
+            else {
+                self.capture_failed(output, queue_handle);
+                continue;
+            };
+            // the output is captured as soon as the compositor has shown it without the frozen frame
+            trace!("  uncovering output {}", output);
+            let surface = &surfaces[&output];
+            if let Some(viewports) = &self.viewports {
+                viewports[&output].set_source(-1.0, -1.0, -1.0, -1.0);
+            }
+            surface.attach(Some(buffer), 0, 0);
+            surface.set_buffer_scale(1);
+            surface.set_buffer_transform(wl_output::Transform::Normal);
+            surface.damage(0, 0, i32::MAX, i32::MAX);
+            surface.frame(queue_handle, output);
+            surface.commit();
+            self.uncover_deadlines
+                .insert(output, Instant::now() + UNCOVER_TIMEOUT);
+        }
+    }
+    // captures the uncovered outputs that weren't shown in time, e.g. because they're off
+    fn capture_uncovered_outputs(&mut self, queue_handle: &QueueHandle<Self>) {
+        let now = Instant::now();
+        let mut due: Vec<i64> = self
+            .uncover_deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(output, _)| *output)
+            .collect();
+        due.sort();
+        for output in due {
+            self.uncover_deadlines.remove(&output);
+            warn!(
+                "Output {} wasn't shown uncovered in time, capturing it anyway",
+                output
+            );
+            if let Err(e) = self.capture_output(output, queue_handle) {
+                error!("Could not capture output {}: {}", output, e);
+                self.capture_failed(output, queue_handle);
+            }
+        }
+    }
+    // swaps in the new frames, outputs that couldn't be captured again get their previous frame back
+    fn finish_refreeze(&mut self, queue_handle: &QueueHandle<Self>) {
+        let outputs = std::mem::take(&mut self.refreeze_outputs);
+        for output in &outputs {
+            let output = *output;
+            let captured = self
+                .buffers
+                .as_ref()
+                .is_some_and(|buffers| buffers.contains_key(&output));
+            if captured {
+                self.blank_outputs.retain(|o| *o != output);
+            } else if let Some(frame) = self.presented_frames.get(&output).cloned() {
+                self.restore_frame(output, frame);
+            } else if self.create_blank_buffer(output, queue_handle).is_some() {
+                // nothing to go back to, cover it instead of leaving it uncovered
+                if !self.blank_outputs.contains(&output) {
+                    self.blank_outputs.push(output);
+                }
+            }
+        }
+        self.present_frames(&outputs);
+        self.refreezing = false;
+        info!("> Screen refrozen");
+    }
+    // puts a frame that was captured before back in place, so that it gets presented
+    fn restore_frame(&mut self, output: i64, frame: CapturedFrame) {
+        vec_insert(&mut self.buffers, output, frame.buffer);
+        vec_insert(&mut self.shm_pools, output, frame.pool);
+        vec_insert(&mut self.shm_buffer_infos, output, frame.info);
+        match frame.flags {
+            Some(flags) => vec_insert(&mut self.screencopy_flags, output, flags),
+            None => {
+                if let Some(flags) = &mut self.screencopy_flags {
+                    flags.remove(&output);
+                }
+            }
+        }
+    }
+    // takes the frame that was just captured out of place, so that the next capture doesn't destroy it
+    fn take_frame(&mut self, output: i64) -> Option<CapturedFrame> {
+        let (Some(buffer), Some(pool), Some(info)) = (
+            self.buffers.as_mut().and_then(|b| b.remove(&output)),
+            self.shm_pools.as_mut().and_then(|p| p.remove(&output)),
+            self.shm_buffer_infos
+                .as_mut()
+                .and_then(|i| i.remove(&output)),
+        ) else {
+            return None;
+        };
+        let flags = self
+            .screencopy_flags
+            .as_mut()
+            .and_then(|f| f.remove(&output));
+        // the buffer was taken out already, only the capture objects get destroyed
+        self.reset_capture(output);
+        Some(CapturedFrame {
+            buffer,
+            pool,
+            info,
+            flags,
+        })
+    }
+    // records a mode advertised by an output & updates its physical size to the mode it's using
+    fn update_mode(&mut self, output: i64, mode: OutputMode) {
+        let modes = self.modes.get_or_insert_with(HashMap::new);
+        let modes = modes.entry(output).or_default();
+        record_mode(modes, mode);
+        let Some(current) = current_mode(modes) else {
+            return;
+        };
+        let previous = match (&self.phys_widths, &self.phys_heights) {
+            (Some(widths), Some(heights)) => widths
+                .get(&output)
+                .copied()
+                .zip(heights.get(&output).copied()),
+            _ => None,
+        };
+        let changed = previous.is_some_and(|size| size != (current.width, current.height));
+        if changed {
+            info!(
+                "> Mode of output {} changed to {}x{}",
+                output, current.width, current.height
+            );
+        }
+        vec_insert(&mut self.phys_widths, output, current.width);
+        vec_insert(&mut self.phys_heights, output, current.height);
+        if changed {
+            self.output_changed(output);
+        }
+    }
+    // marks a frozen output to be captured again, because its frame doesn't match it anymore
+    fn output_changed(&mut self, output: i64) {
+        if !self.frozen
+            || !self.configured_surfaces.contains_key(&output)
+            || self.changed_outputs.contains(&output)
+        {
+            return;
+        }
+        info!("> Output {} changed, capturing it again", output);
+        self.changed_outputs.push(output);
+    }
+    // moves & resizes the surfaces of the changed outputs, then captures them again
+    fn follow_output_changes(&mut self, queue_handle: &QueueHandle<Self>) {
+        let mut outputs = Vec::new();
+        for output in std::mem::take(&mut self.changed_outputs) {
+            let Some(region) = self.output_region(output) else {
+                warn!(
+                    "Output {} is outside of the geometry now, keeping its previous frame",
+                    output
+                );
+                continue;
+            };
+            vec_insert(&mut self.regions, output, region);
+            if let Some(layer_surface) = self.layer_surfaces.as_ref().and_then(|l| l.get(&output)) {
+                if self.geometry.is_some() {
+                    layer_surface.set_margin(region.y, 0, 0, region.x);
+                    layer_surface.set_size(region.width as u32, region.height as u32);
+                }
+            }
+            if let Some(viewport) = self.viewports.as_ref().and_then(|v| v.get(&output)) {
+                viewport.set_destination(region.width, region.height);
+            }
+            if self.is_mirror_target(output) {
+                // nothing to capture, the mirrored frame only has to be placed again
+                self.present_frame(output);
+                continue;
+            }
+            outputs.push(output);
+        }
+        if outputs.is_empty() {
+            return;
+        }
+        outputs.sort();
+        self.refreeze_outputs(outputs, queue_handle);
+    }
+    // stores the logical size of an output, the first one makes the output ready
+    fn set_logical_size(&mut self, output: i64, width: i32, height: i32) {
+        let first_size = !self
+            .widths
+            .as_ref()
+            .is_some_and(|w| w.contains_key(&output));
+        let changed = !first_size
+            && (self.widths.as_ref().and_then(|w| w.get(&output)) != Some(&width)
+                || self.heights.as_ref().and_then(|h| h.get(&output)) != Some(&height));
+        // save the width & height of this output under the same key as this output
+        vec_insert(&mut self.widths, output, width);
+        vec_insert(&mut self.heights, output, height);
+        if changed {
+            self.output_changed(output);
+        }
+        if first_size {
+            self.outputs_ready += 1;
+            if self.frozen {
+                // plugged in while frozen
+                self.hotplugged_outputs.push(output);
+            }
+        }
+    }
+    // logical size of an output worked out from its mode, scale & transform, for when there's no zxdg_output_v1
+    fn derived_logical_size(&self, output: i64) -> Option<(i32, i32)> {
+        let mode = current_mode(self.modes.as_ref()?.get(&output)?)?;
+        let scale = match &self.output_scales {
+            Some(scales) => scales.get(&output).copied().unwrap_or(1).max(1),
+            None => 1,
+        };
+        let transform = match &self.transforms {
+            Some(transforms) => transforms
+                .get(&output)
+                .copied()
+                .unwrap_or(wl_output::Transform::Normal),
+            None => wl_output::Transform::Normal,
+        };
+        let (width, height) = transformed_size((mode.width, mode.height), transform);
+        Some((width / scale, height / scale))
+    }
+    // updates the logical size of an output from its mode, scale & transform
+    fn derive_logical_size(&mut self, output: i64) {
+        if self.xdg_output_manager.is_some() {
+            return;
+        }
+        if let Some((width, height)) = self.derived_logical_size(output) {
+            trace!("  derived logical size {}x{}", width, height);
+            self.set_logical_size(output, width, height);
+        }
+    }
+    // part of an output that gets frozen in output-local logical coordinates, if any
+    fn output_region(&self, output: i64) -> Option<Region> {
+        let (Some(widths), Some(heights)) = (&self.widths, &self.heights) else {
+            return None;
+        };
+        let (x, y) = match &self.positions {
+            Some(positions) => positions.get(&output).copied().unwrap_or_default(),
+            None => (0, 0),
+        };
+        let bounds = Region {
+            x,
+            y,
+            width: *widths.get(&output)?,
+            height: *heights.get(&output)?,
+        };
+        match self.geometry {
+            // freeze every output completely
+            None => Some(Region {
+                x: 0,
+                y: 0,
+                ..bounds
+            }),
+            // only freeze the part of the output that lies within the geometry
+            Some(geometry) => {
+                let intersection = geometry.intersect(&bounds)?;
+                Some(Region {
+                    x: intersection.x - x,
+                    y: intersection.y - y,
+                    ..intersection
+                })
+            }
+        }
+    }
+    // destroys everything that belongs to an output that was unplugged
+    fn remove_output(&mut self, output: i64) {
+        self.reset_capture(output);
+        // roles & add-ons have to be destroyed before their surface
+        if let Some(layer_surface) = vec_remove(&mut self.layer_surfaces, &output) {
+            layer_surface.destroy();
+        }
+        if let Some(toplevel) = vec_remove(&mut self.toplevels, &output) {
+            toplevel.destroy();
+        }
+        if let Some(xdg_surface) = vec_remove(&mut self.xdg_surfaces, &output) {
+            xdg_surface.destroy();
+        }
+        if let Some(viewport) = vec_remove(&mut self.viewports, &output) {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = vec_remove(&mut self.fractional_scales, &output) {
+            fractional_scale.destroy();
+        }
+        if let Some((surface, subsurface, viewport)) = self.mirror_surfaces.remove(&output) {
+            viewport.destroy();
+            subsurface.destroy();
+            surface.destroy();
+        }
+        if let Some((surface, subsurface)) = self.hint_surfaces.remove(&output) {
+            subsurface.destroy();
+            surface.destroy();
+        }
+        if let Some((buffer, pool)) = self.hint_buffers.remove(&output) {
+            buffer.destroy();
+            pool.destroy();
+        }
+        if self.pointer_output == Some(output) {
+            self.pointer_output = None;
+        }
+        if let Some(surface) = vec_remove(&mut self.surfaces, &output) {
+            surface.destroy();
+        }
+        if let Some(xdg_output) = vec_remove(&mut self.xdg_outputs, &output) {
+            xdg_output.destroy();
+        }
+
+        // frames that were kept around for this output
+        let mut frames: Vec<CapturedFrame> = Vec::new();
+        frames.extend(self.presented_frames.remove(&output));
+        frames.extend(self.burst_frames.remove(&output).unwrap_or_default());
+        frames.extend(
+            self.replay_frames
+                .remove(&output)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(_, frame)| frame),
+        );
+        frames.extend(self.spare_frames.remove(&output).unwrap_or_default());
+        let mut destroyed: Vec<wl_buffer::WlBuffer> = Vec::new();
+        for frame in frames {
+            if !destroyed.contains(&frame.buffer) {
+                frame.buffer.destroy();
+                frame.pool.destroy();
+                destroyed.push(frame.buffer);
+            }
+        }
+        // anything reset_capture kept because it was presented
+        vec_remove(&mut self.buffers, &output);
+        vec_remove(&mut self.shm_pools, &output);
+        vec_remove(&mut self.shm_buffer_infos, &output);
+
+        // the output may disappear before the screen is frozen
+        if self
+            .widths
+            .as_ref()
+            .is_some_and(|w| w.contains_key(&output))
+        {
+            self.outputs_ready -= 1;
+        }
+        if self.captured_outputs.contains(&output) {
+            self.frames_ready -= 1;
+        }
+        self.output_count -= 1;
+
+        vec_remove(&mut self.positions, &output);
+        vec_remove(&mut self.widths, &output);
+        vec_remove(&mut self.heights, &output);
+        vec_remove(&mut self.names, &output);
+        vec_remove(&mut self.descriptions, &output);
+        vec_remove(&mut self.makes, &output);
+        vec_remove(&mut self.models, &output);
+        vec_remove(&mut self.output_scales, &output);
+        self.buffer_scales.remove(&output);
+        vec_remove(&mut self.regions, &output);
+        vec_remove(&mut self.modes, &output);
+        vec_remove(&mut self.phys_widths, &output);
+        vec_remove(&mut self.phys_heights, &output);
+        vec_remove(&mut self.transforms, &output);
+        vec_remove(&mut self.frame_transforms, &output);
+        vec_remove(&mut self.scales, &output);
+        self.capture_attempts.remove(&output);
+        self.capture_retry_deadlines.remove(&output);
+        self.uncover_deadlines.remove(&output);
+        self.configured_surfaces.remove(&output);
+        self.blank_outputs.retain(|o| *o != output);
+        self.captured_outputs.retain(|o| *o != output);
+        self.change_baselines.retain(|o| *o != output);
+        self.hotplugged_outputs.retain(|o| *o != output);
+        self.pending_outputs.retain(|o| *o != output);
+        self.changed_outputs.retain(|o| *o != output);
+        self.refreeze_outputs.retain(|o| *o != output);
+        self.output_globals.remove(&output);
+
+        if let Some(wl_output) = vec_remove(&mut self.outputs, &output) {
+            if wl_output.version() >= 3 {
+                wl_output.release();
+            }
+        }
+    }
+    // layout of an output, built from everything the compositor told us about it
+    fn output_info(&self, output: i64) -> OutputInfo {
+        let get =
+            |map: &Option<HashMap<i64, String>>| map.as_ref().and_then(|m| m.get(&output)).cloned();
+        let get_i32 =
+            |map: &Option<HashMap<i64, i32>>| map.as_ref().and_then(|m| m.get(&output)).copied();
+        let (x, y) = match &self.positions {
+            Some(positions) => positions.get(&output).copied().unwrap_or_default(),
+            None => (0, 0),
+        };
+        let transform = match &self.transforms {
+            Some(transforms) => transforms
+                .get(&output)
+                .copied()
+                .unwrap_or(wl_output::Transform::Normal),
+            None => wl_output::Transform::Normal,
+        };
+        let mode = self
+            .modes
+            .as_ref()
+            .and_then(|m| m.get(&output))
+            .and_then(|modes| current_mode(modes));
+        let width = get_i32(&self.widths).unwrap_or_default();
+        let height = get_i32(&self.heights).unwrap_or_default();
+        // the logical size is the mode divided by the scale, which may be fractional
+        let scale = match mode {
+            Some(mode) if width > 0 => {
+                let (mode_width, _) = transformed_size((mode.width, mode.height), transform);
+                mode_width as f64 / width as f64
+            }
+            _ => get_i32(&self.output_scales).unwrap_or(1) as f64,
+        };
+        OutputInfo {
+            name: get(&self.names),
+            description: get(&self.descriptions),
+            make: get(&self.makes),
+            model: get(&self.models),
+            x,
+            y,
+            width,
+            height,
+            mode: mode.map(|mode| ModeInfo {
+                width: mode.width,
+                height: mode.height,
+                refresh: mode.refresh as f64 / 1000.0,
+            }),
+            scale,
+            transform: transform_name(transform),
+        }
+    }
+    // layout of every output, in the order they were bound
+    fn output_layout(&self) -> Vec<OutputInfo> {
+        let mut outputs: Vec<i64> = match &self.outputs {
+            Some(outputs) => outputs.keys().copied().collect(),
+            None => Vec::new(),
+        };
+        outputs.sort();
+        outputs
+            .into_iter()
+            .map(|output| self.output_info(output))
+            .collect()
+    }
+    // whether an output was selected with --output & --exclude-output
+    fn output_selected(&self, output: i64) -> bool {
+        let name = self.names.as_ref().and_then(|n| n.get(&output));
+        let description = self.descriptions.as_ref().and_then(|d| d.get(&output));
+        let matches = |pattern: &String| {
+            name.is_some_and(|name| glob_match(pattern, name))
+                || description.is_some_and(|description| glob_match(pattern, description))
+        };
+        if let Some(focused) = &self.focused_output {
+            // the compositor reports the connector name, descriptions aren't matched against
+            if name != Some(focused) {
+                return false;
+            }
+        }
+        (self.include_outputs.is_empty() || self.include_outputs.iter().any(matches))
+            && !self.exclude_outputs.iter().any(matches)
+    }
+    // outputs that have a region to freeze, in the order they were bound
+    fn frozen_outputs(&self) -> Vec<i64> {
+        let mut frozen_outputs: Vec<i64> = match &self.regions {
+            Some(regions) => regions.keys().copied().collect(),
+            None => Vec::new(),
+        };
+        frozen_outputs.sort();
+        frozen_outputs
+    }
+    // captures every frozen output once more, outputs that are covered already stay covered
+    fn capture_round(&mut self, queue_handle: &QueueHandle<Self>) -> Result<(), Box<dyn Error>> {
+        self.frames_ready = 0;
+        self.captured_outputs.clear();
+        self.capture_attempts.clear();
+        self.capture_retry_deadlines.clear();
+        for output in self.frozen_outputs() {
+            if self.blank_outputs.contains(&output) {
+                self.captured_outputs.push(output);
+                self.frames_ready += 1;
+                continue;
+            }
+            self.capture_output(output, queue_handle)?;
+        }
+        Ok(())
+    }
+    // moves the frames that were just captured aside, so that the next frames of a burst can be captured
+    fn stash_burst_frames(&mut self) {
+        for output in self.captured_outputs.clone() {
+            // blank outputs are only covered once
+            if let Some(frame) = self.take_frame(output) {
+                self.burst_frames.entry(output).or_default().push(frame);
+            }
+        }
+    }
+    // moves the frames that were just captured into the replay ring, the oldest frames make room for them
+    fn record_replay_frames(&mut self, capacity: usize) {
+        let now = Instant::now();
+        for output in self.captured_outputs.clone() {
+            let Some(frame) = self.take_frame(output) else {
+                continue;
+            };
+            let ring = self.replay_frames.entry(output).or_default();
+            ring.push_back((now, frame));
+            if ring.len() > capacity {
+                if let Some((_, oldest)) = ring.pop_front() {
+                    self.spare_frames.entry(output).or_default().push(oldest);
+                }
+            }
+        }
+    }
+    // puts the frames that were on screen the configured amount of time ago in place, the rest is destroyed
+    fn select_replay_frames(&mut self) {
+        let now = Instant::now();
+        let delay = Duration::from_millis(self.replay.unwrap_or_default());
+        let mut replayed = Vec::new();
+        for (output, mut ring) in std::mem::take(&mut self.replay_frames) {
+            // newest frame that is at least as old as the delay, the oldest one if there is none
+            let index = ring
+                .iter()
+                .rposition(|(captured, _)| now.duration_since(*captured) >= delay)
+                .unwrap_or(0);
+            let Some((captured, frame)) = ring.remove(index) else {
+                continue;
+            };
+            debug!(
+                "| Replaying frame of output {} from {}ms ago",
+                output,
+                now.duration_since(captured).as_millis()
+            );
+            for (_, frame) in ring {
+                frame.buffer.destroy();
+                frame.pool.destroy();
+            }
+            self.restore_frame(output, frame);
+            replayed.push(output);
+        }
+        for (_, spares) in self.spare_frames.drain() {
+            for frame in spares {
+                frame.buffer.destroy();
+                frame.pool.destroy();
+            }
+        }
+        // outputs of which nothing was recorded are left alone
+        if let Some(regions) = &mut self.regions {
+            regions.retain(|output, _| replayed.contains(output));
+        }
+        self.frames_ready = replayed.len() as i32;
+        self.captured_outputs = replayed;
+    }
+    // presents the previous or next frame of the burst on every output
+    fn step_burst(&mut self, step: isize) {
+        let count = self.burst_frames.values().map(Vec::len).max().unwrap_or(0);
+        if count == 0 {
+            return;
+        }
+        self.burst_index = (self.burst_index as isize + step).rem_euclid(count as isize) as usize;
+        info!("> Showing frame {}/{}", self.burst_index + 1, count);
+        let mut outputs: Vec<i64> = self.burst_frames.keys().copied().collect();
+        outputs.sort();
+        for output in outputs {
+            self.load_burst_frame(output);
+            // surfaces that aren't configured yet present the loaded frame once they are
+            if self.presented_frames.contains_key(&output) {
+                self.present_frame(output);
+            }
+        }
+    }
+    // puts the selected frame of the burst of an output in place
+    fn load_burst_frame(&mut self, output: i64) {
+        let Some(frame) = self
+            .burst_frames
+            .get(&output)
+            .and_then(|frames| frames.get(self.burst_index).or(frames.last()))
+            .cloned()
+        else {
+            return;
+        };
+        self.restore_frame(output, frame);
+    }
+    // keeps the frames that are shown right now & throws away the rest of the burst
+    fn pick_burst_frame(&mut self) {
+        if self.burst_frames.is_empty() {
+            return;
+        }
+        info!("> Picked frame {}", self.burst_index + 1);
+        for (output, frames) in self.burst_frames.drain() {
+            let kept = self.buffers.as_ref().and_then(|b| b.get(&output));
+            for frame in frames {
+                if Some(&frame.buffer) != kept {
+                    frame.buffer.destroy();
+                    frame.pool.destroy();
+                }
+            }
+        }
+        self.frame_picked = true;
+    }
+    // puts a dimmed hint with its number on top of every frozen output that doesn't have one yet
+    fn show_output_hints(&mut self, queue_handle: &QueueHandle<Self>) {
+        let (Some((compositor, _)), Some((subcompositor, _)), Some(surfaces), Some(regions)) = (
+            &self.compositor,
+            &self.subcompositor,
+            &self.surfaces,
+            &self.regions,
+        ) else {
+            warn!("No WlSubcompositor loaded, can't show which output is which");
+            return;
+        };
+        for (index, output) in self.frozen_outputs().into_iter().enumerate() {
+            // outputs that are plugged in while frozen get theirs once they're captured
+            if self.hint_surfaces.contains_key(&output) || self.pending_outputs.contains(&output) {
+                continue;
+            }
+            let Some(parent) = surfaces.get(&output) else {
+                continue;
+            };
+            let region = regions[&output];
+            let Ok(mut tmp) = tempfile() else {
+                error!("Unable to create tempfile");
+                return;
+            };
+            let pixels = render_hint(region.width, region.height, index + 1);
+            if tmp.write_all(&pixels).is_err() {
+                error!("Could not write the hint of output {}", output);
+                continue;
+            }
+            let Some((shm, _)) = &self.shm else {
+                error!("No WlShm loaded");
+                return;
+            };
+            let pool = shm.create_pool(tmp.as_fd(), pixels.len() as i32, queue_handle, ());
+            let buffer = pool.create_buffer(
+                0,
+                region.width,
+                region.height,
+                region.width * 4,
+                wl_shm::Format::Argb8888,
+                queue_handle,
+                (),
+            );
+
+            // the hint sits on top of the frozen frame, clicks go through it
+            let surface = compositor.create_surface(queue_handle, ());
+            let input_region = compositor.create_region(queue_handle, ());
+            surface.set_input_region(Some(&input_region));
+            input_region.destroy();
+            let subsurface = subcompositor.get_subsurface(&surface, parent, queue_handle, ());
+            subsurface.set_position(0, 0);
+            subsurface.set_desync();
+            // the subsurface is added to the parent with its next commit
+            parent.commit();
+
+            self.hint_surfaces.insert(output, (surface, subsurface));
+            self.hint_buffers.insert(output, (buffer, pool));
+        }
+        self.update_output_hints();
+    }
+    // shows the hints on every output but the one the pointer is on
+    fn update_output_hints(&self) {
+        for (output, (surface, _)) in &self.hint_surfaces {
+            if self.pointer_output == Some(*output) {
+                surface.attach(None, 0, 0);
+            } else {
+                surface.attach(self.hint_buffers.get(output).map(|(b, _)| b), 0, 0);
+                surface.damage(0, 0, i32::MAX, i32::MAX);
+            }
+            surface.commit();
+        }
+    }
+    // output of which the surface is the given one
+    fn surface_output(&self, surface: &wl_surface::WlSurface) -> Option<i64> {
+        self.surfaces
+            .as_ref()?
+            .iter()
+            .find(|(_, s)| *s == surface)
+            .map(|(output, _)| *output)
+    }
+    fn keyboard_entered(&mut self) {
+        self.keyboard_focus = true;
+        self.keyboard_entered_at = Some(Instant::now());
+    }
+    // whether the click that's being released is the one that gave the surface keyboard focus
+    // compositors focus an on-demand surface on the press, its enter may arrive right before or after the press
+    fn focused_by_click(&mut self) -> bool {
+        let (Some(entered), Some(pressed)) = (self.keyboard_entered_at, self.button_pressed_at)
+        else {
+            return false;
+        };
+        let apart = entered
+            .saturating_duration_since(pressed)
+            .max(pressed.saturating_duration_since(entered));
+        if apart > FOCUS_CLICK_WINDOW {
+            // e.g. focus came back after a launcher on top of the freeze closed
+            return false;
+        }
+        self.keyboard_entered_at = None;
+        true
+    }
+    fn pointer_button(&mut self, button_state: wayland_client::WEnum<wl_pointer::ButtonState>) {
+        if button_state != wayland_client::WEnum::Value(wl_pointer::ButtonState::Released) {
+            self.button_pressed_at = Some(Instant::now());
+            return;
+        }
+        if self.pick_output {
+            if let Some(output) = self.pointer_output {
+                info!("> Output {} clicked", output);
+                self.picked_output = Some(output);
+            }
+            return;
+        }
+        if self.keyboard_mode == KeyboardMode::OnDemand
+            && (!self.keyboard_focus || self.focused_by_click())
+        {
+            // the click gives the surface keyboard focus, the next one exits
+            trace!("  surface focused by click");
+            return;
+        }
+        info!("> Mouse button released - exiting...");
+        self.exit = true;
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        proxy: &wl_registry::WlRegistry,
+        event: <wl_registry::WlRegistry as wayland_client::Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } => {
+                debug!("| Received wl_registry::Event::Global: {interface} v{version}");
+                let version = negotiate_version(&interface, version);
+                if interface == wl_compositor::WlCompositor::interface().name
+                    && state.compositor.is_none()
+                {
+                    // wl_compositor
+                    info!("> Bound: {interface} v{version}");
+                    state.compositor = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == wl_output::WlOutput::interface().name {
+                    // wl_output
+                    info!("> Bound: {interface} v{version}");
+                    // outputs can be plugged in at any time, keys are never reused
+                    let index = state.bound_outputs;
+                    state.bound_outputs += 1;
+                    state.output_count += 1;
+                    vec_insert(
+                        &mut state.outputs,
+                        index as i64,
+                        proxy.bind(name, version, queue_handle, index),
+                    );
+                    state.output_globals.insert(index as i64, name);
+                } else if interface == wl_seat::WlSeat::interface().name && state.seat.is_none() {
+                    // wl_seat
+                    info!("> Bound: {interface} v{version}");
+                    let seat: wl_seat::WlSeat = proxy.bind(name, version, queue_handle, ());
+                    state.pointer = Some(seat.get_pointer(queue_handle, ()));
+                    if state.keyboard_mode != KeyboardMode::None {
+                        state.keyboard = Some(seat.get_keyboard(queue_handle, ()));
+                    }
+                    state.seat = Some((seat, name));
+                } else if interface == wl_shm::WlShm::interface().name && state.shm.is_none() {
+                    // wl_shm
+                    info!("> Bound: {interface} v{version}");
+                    state.shm = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == WpFractionalScaleManagerV1::interface().name
+                    && state.fs_manager.is_none()
+                {
+                    // wp_fractional_scale_manager_v1
+                    info!("> Bound: {interface} v{version}");
+                    state.fs_manager = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == wl_subcompositor::WlSubcompositor::interface().name
+                    && state.subcompositor.is_none()
+                {
+                    // wl_subcompositor
+                    info!("> Bound: {interface} v{version}");
+                    state.subcompositor = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == WpViewporter::interface().name && state.viewporter.is_none()
+                {
+                    // wp_viewporter
+                    state.viewporter = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == ZxdgOutputManagerV1::interface().name
+                    && state.xdg_output_manager.is_none()
+                {
+                    // zxdg_output_manager_v1
+                    info!("> Bound: {interface} v{version}");
+                    state.xdg_output_manager =
+                        Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == ZwlrScreencopyManagerV1::interface().name
+                    && state.screencopy_manager.is_none()
+                {
+                    // zwlr_screencopy_manager_v1
+                    info!("> Bound: {interface} v{version}");
+                    state.screencopy_manager =
+                        Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == zwlr_layer_shell_v1::ZwlrLayerShellV1::interface().name
+                    && state.layer_shell.is_none()
+                {
+                    // zwlr_layer_shell_v1
+                    info!("> Bound: {interface} v{version}");
+                    state.layer_shell = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == XdgWmBase::interface().name && state.wm_base.is_none() {
+                    // xdg_wm_base
+                    info!("> Bound: {interface} v{version}");
+                    state.wm_base = Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == ExtOutputImageCaptureSourceManagerV1::interface().name
+                    && state.capture_source_manager.is_none()
+                {
+                    // ext_output_image_capture_source_manager_v1
+                    info!("> Bound: {interface} v{version}");
+                    state.capture_source_manager =
+                        Some((proxy.bind(name, version, queue_handle, ()), name));
+                } else if interface == ExtImageCopyCaptureManagerV1::interface().name
+                    && state.copy_capture_manager.is_none()
+                {
+                    // ext_image_copy_capture_manager_v1
+                    info!("> Bound: {interface} v{version}");
+                    state.copy_capture_manager =
+                        Some((proxy.bind(name, version, queue_handle, ()), name));
+                };
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                debug!("| Received wl_registry::Event::GlobalRemove");
+                let removed_output = state
+                    .output_globals
+                    .iter()
+                    .find(|(_, global)| **global == name)
+                    .map(|(output, _)| *output);
+                if let Some(output) = removed_output {
+                    info!("> Output {} was removed", output);
+                    state.remove_output(output);
+                } else if state.compositor.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("WlCompositor was removed");
+                    state.compositor = None;
+                } else if state.seat.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("WlSeat was removed");
+                    state.seat = None;
+                } else if state.shm.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("WlShm was removed");
+                    state.shm = None;
+                } else if state.fs_manager.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("WpFractionalScaleManagerV1 was removed");
+                    state.fs_manager = None;
+                } else if state.viewporter.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("WpViewporter was removed");
+                    state.viewporter = None;
+                } else if state
+                    .subcompositor
+                    .as_ref()
+                    .is_some_and(|(_, n)| *n == name)
+                {
+                    warn!("WlSubcompositor was removed");
+                    state.subcompositor = None;
+                } else if state
+                    .xdg_output_manager
+                    .as_ref()
+                    .is_some_and(|(_, n)| *n == name)
+                {
+                    warn!("ZxdgOutputManagerV1 was removed");
+                    state.xdg_output_manager = None;
+                } else if state
+                    .screencopy_manager
+                    .as_ref()
+                    .is_some_and(|(_, n)| *n == name)
+                {
+                    warn!("ZwlrScreencopyManagerV1 was removed");
+                    state.screencopy_manager = None;
+                } else if state.wm_base.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("XdgWmBase was removed");
+                    state.wm_base = None;
+                } else if state.layer_shell.as_ref().is_some_and(|(_, n)| *n == name) {
+                    warn!("ZwlrLayerShellV1 was removed");
+                    state.layer_shell = None;
+                } else if state
+                    .capture_source_manager
+                    .as_ref()
+                    .is_some_and(|(_, n)| *n == name)
+                {
+                    warn!("ExtOutputImageCaptureSourceManagerV1 was removed");
+                    state.capture_source_manager = None;
+                } else if state
+                    .copy_capture_manager
+                    .as_ref()
+                    .is_some_and(|(_, n)| *n == name)
+                {
+                    warn!("ExtImageCopyCaptureManagerV1 was removed");
+                    state.copy_capture_manager = None;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, usize> for AppData {
+    fn event(
+        state: &mut Self,
+        proxy: &wl_output::WlOutput,
+        event: <wl_output::WlOutput as Proxy>::Event,
+        data: &usize,
+        _connection: &wayland_client::Connection,
+        queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            wl_output::Event::Mode {
+                flags,
+                width,
+                height,
+                refresh,
+            } => {
+                debug!("| Received wl_output::Event::Mode for output {}", data);
+                // describes an available output mode for the output, sent again when the current mode changes
+                let flags = flags.into_result().unwrap_or(wl_output::Mode::empty());
+                trace!("  mode: {}x{}@{} {:?}", width, height, refresh, flags);
+                state.update_mode(
+                    *data as i64,
+                    OutputMode {
+                        width,
+                        height,
+                        refresh,
+                        flags,
+                    },
+                );
+                // wl_output v1 has no done event
+                if proxy.version() < 2 {
+                    state.derive_logical_size(*data as i64);
+                }
+            }
+            wl_output::Event::Done => {
+                debug!("| Received wl_output::Event::Done for output {}", data);
+                // all properties of the output have been sent
+                state.derive_logical_size(*data as i64);
+            }
+            wl_output::Event::Scale { factor } => {
+                debug!("| Received wl_output::Event::Scale for output {}", data);
+                // integer scale of the output
+                let previous = state
+                    .output_scales
+                    .as_ref()
+                    .and_then(|s| s.get(&(*data as i64)).copied());
+                vec_insert(&mut state.output_scales, *data as i64, factor);
+                if previous.is_some_and(|f| f != factor) {
+                    state.output_changed(*data as i64);
+                }
+                // without a viewport, the frame is presented at this scale
+                if state.viewporter.is_none()
+                    && !state.buffer_scales.contains_key(&(*data as i64))
+                    && state.configured_surfaces.contains_key(&(*data as i64))
+                    && !state.refreeze_outputs.contains(&(*data as i64))
+                {
+                    state.present_frame(*data as i64);
+                }
+            }
+            wl_output::Event::Name { name } => {
+                debug!("| Received wl_output::Event::Name for output {}", data);
+                // name of the output, e.g. the connector it's plugged into
+                trace!("  name: {}", name);
+                vec_insert(&mut state.names, *data as i64, name);
+            }
+            wl_output::Event::Description { description } => {
+                debug!(
+                    "| Received wl_output::Event::Description for output {}",
+                    data
+                );
+                // human-readable description of the output, e.g. its make & model
+                trace!("  description: {}", description);
+                vec_insert(&mut state.descriptions, *data as i64, description);
+            }
+            wl_output::Event::Geometry {
+                x,
+                y,
+                physical_width: _,
+                physical_height: _,
+                subpixel: _,
+                make,
+                model,
+                transform,
+            } => {
+                debug!("| Received wl_output::Event::Geometry for output {}", data);
+                // describes transformations that clients and compositors apply to buffer contents
+                vec_insert(&mut state.makes, *data as i64, make);
+                vec_insert(&mut state.models, *data as i64, model);
+                // zxdg_output_v1 sends the logical position as well, which takes precedence
+                if state.xdg_output_manager.is_none()
+                    || !state
+                        .positions
+                        .as_ref()
+                        .is_some_and(|p| p.contains_key(&(*data as i64)))
+                {
+                    let previous = state
+                        .positions
+                        .as_ref()
+                        .and_then(|p| p.get(&(*data as i64)).copied());
+                    vec_insert(&mut state.positions, *data as i64, (x, y));
+                    if state.geometry.is_some() && previous.is_some_and(|p| p != (x, y)) {
+                        state.output_changed(*data as i64);
+                    }
+                }
+
+                let transform = transform.into_result().unwrap();
+                let previous = state
+                    .transforms
+                    .as_ref()
+                    .and_then(|t| t.get(&(*data as i64)).copied());
+                vec_insert(&mut state.transforms, *data as i64, transform);
+                if previous.is_some_and(|t| t != transform) {
+                    info!("> Output {} was rotated", data);
+                    state.output_changed(*data as i64);
+                }
+
+                // geometry is sent again whenever it changes, the surface only has to be created once
+                if state
+                    .surfaces
+                    .as_ref()
+                    .is_some_and(|s| s.contains_key(&(*data as i64)))
+                {
                     return;
-                };
-                // create an xdg_output object for this wl_output
-                xdg_output_manager.get_xdg_output(proxy, &queue_handle, *data as i64);
+                }
+
+                match &state.xdg_output_manager {
+                    // create an xdg_output object for this wl_output
+                    Some((xdg_output_manager, _)) => vec_insert(
+                        &mut state.xdg_outputs,
+                        *data as i64,
+                        xdg_output_manager.get_xdg_output(proxy, queue_handle, *data as i64),
+                    ),
+                    // the logical size gets derived from the mode once the output is done
+                    None => warn!(
+                        "No ZxdgOutputManagerV1 loaded, deriving the size of output {} from its mode",
+                        data
+                    ),
+                }
 
                 // create a surface for this output & store it
                 let Some((compositor, _)) = &state.compositor else {
@@ -278,7 +2732,7 @@ impl Dispatch<wl_output::WlOutput, usize> for AppData {
                 vec_insert(
                     &mut state.surfaces,
                     *data as i64,
-                    compositor.create_surface(&queue_handle, ()),
+                    compositor.create_surface(queue_handle, ()),
                 );
             }
             _ => {}
@@ -288,13 +2742,59 @@ impl Dispatch<wl_output::WlOutput, usize> for AppData {
 
 impl Dispatch<wl_surface::WlSurface, ()> for AppData {
     fn event(
-        _state: &mut Self,
-        _proxy: &wl_surface::WlSurface,
-        _event: <wl_surface::WlSurface as Proxy>::Event,
+        state: &mut Self,
+        proxy: &wl_surface::WlSurface,
+        event: <wl_surface::WlSurface as Proxy>::Event,
         _data: &(),
         _connection: &wayland_client::Connection,
         _queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
+        if let wl_surface::Event::PreferredBufferScale { factor } = event {
+            let Some(output) = state
+                .surfaces
+                .as_ref()
+                .and_then(|s| s.iter().find(|(_, surface)| *surface == proxy))
+                .map(|(output, _)| *output)
+            else {
+                return;
+            };
+            debug!(
+                "| Received wl_surface::Event::PreferredBufferScale for output {}",
+                output
+            );
+            // integer scale the compositor would like the buffers of this surface to have
+            trace!("  scale: {}", factor);
+            if state.buffer_scales.insert(output, factor) != Some(factor)
+                && state.viewporter.is_none()
+                && state.configured_surfaces.contains_key(&output)
+                && !state.refreeze_outputs.contains(&output)
+            {
+                state.present_frame(output);
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, i64> for AppData {
+    fn event(
+        state: &mut Self,
+        _proxy: &wl_callback::WlCallback,
+        event: <wl_callback::WlCallback as Proxy>::Event,
+        data: &i64,
+        _connection: &wayland_client::Connection,
+        queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            debug!("| Received wl_callback::Event::Done for output {}", data);
+            // the uncovered output has been shown, capture it again
+            if state.uncover_deadlines.remove(data).is_none() {
+                return;
+            }
+            if let Err(e) = state.capture_output(*data, queue_handle) {
+                error!("Could not capture output {}: {}", data, e);
+                state.capture_failed(*data, queue_handle);
+            }
+        }
     }
 }
 
@@ -320,19 +2820,26 @@ impl Dispatch<wl_pointer::WlPointer, ()> for AppData {
         _queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
+            wl_pointer::Event::Enter { surface, .. } => {
+                debug!("| Received wl_pointer::Event::Enter");
+                // the pointer moved onto one of our surfaces
+                state.pointer_output = state.surface_output(&surface);
+                state.update_output_hints();
+            }
+            wl_pointer::Event::Leave { .. } => {
+                debug!("| Received wl_pointer::Event::Leave");
+                state.pointer_output = None;
+                state.update_output_hints();
+            }
             wl_pointer::Event::Button {
                 state: button_state,
                 ..
             } => {
                 debug!("| Received wl_pointer::Event::Button");
                 // pointer button event
-                if button_state != wayland_client::WEnum::Value(wl_pointer::ButtonState::Released) {
-                    return;
-                }
-                info!("> Mouse button released - exiting...");
-                state.exit = true;
+                state.pointer_button(button_state);
             }
-            _ => {}
+            _ => (),
         }
     }
 }
@@ -374,6 +2881,15 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for AppData {
                 state.kbstate = Some(xkb::State::new(&keymap));
                 state.keymap = Some(keymap);
             }
+            wl_keyboard::Event::Enter { .. } => {
+                debug!("| Received wl_keyboard::Event::Enter");
+                state.keyboard_entered();
+            }
+            wl_keyboard::Event::Leave { .. } => {
+                debug!("| Received wl_keyboard::Event::Leave");
+                state.keyboard_focus = false;
+                state.keyboard_entered_at = None;
+            }
             wl_keyboard::Event::Key {
                 key,
                 state: key_state,
@@ -389,11 +2905,34 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for AppData {
                     error!("No xkb State loaded");
                     return;
                 };
-                if xkb::State::key_get_one_sym(kbstate, xkb::Keycode::new(key + 8))
-                    == xkb::Keysym::Escape
-                {
-                    info!("> Escape pressed - exiting...");
-                    state.exit = true;
+                let keysym = xkb::State::key_get_one_sym(kbstate, xkb::Keycode::new(key + 8));
+                if state.pick_output {
+                    // number keys pick the output with that number, keypad digits work as well
+                    let digit = char::from_u32(xkb::keysym_to_utf32(keysym))
+                        .and_then(|c| c.to_digit(10))
+                        .filter(|digit| *digit > 0);
+                    if let Some(digit) = digit {
+                        let outputs = state.frozen_outputs();
+                        if let Some(output) = outputs.get(digit as usize - 1) {
+                            info!("> {} pressed - picking output {}", digit, output);
+                            state.picked_output = Some(*output);
+                        }
+                        return;
+                    }
+                }
+                match keysym {
+                    xkb::Keysym::Escape => {
+                        info!("> Escape pressed - exiting...");
+                        state.exit = true;
+                    }
+                    xkb::Keysym::r => {
+                        info!("> R pressed - refreezing...");
+                        state.refreeze_requested = true;
+                    }
+                    xkb::Keysym::Left => state.step_burst(-1),
+                    xkb::Keysym::Right => state.step_burst(1),
+                    xkb::Keysym::Return => state.pick_burst_frame(),
+                    _ => (),
                 };
             }
             _ => (),
@@ -429,13 +2968,21 @@ impl Dispatch<wl_shm_pool::WlShmPool, ()> for AppData {
 
 impl Dispatch<wl_shm::WlShm, ()> for AppData {
     fn event(
-        _state: &mut Self,
+        state: &mut Self,
         _proxy: &wl_shm::WlShm,
-        _event: <wl_shm::WlShm as Proxy>::Event,
+        event: <wl_shm::WlShm as Proxy>::Event,
         _data: &(),
         _connection: &wayland_client::Connection,
         _queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
+        if let wl_shm::Event::Format { format } = event {
+            debug!("| Received wl_shm::Event::Format");
+            // informs the client about a valid pixel format that can be used for buffers
+            if let Ok(format) = format.into_result() {
+                trace!("  supported format: {:?}", format);
+                state.shm_supported_formats.push(format);
+            }
+        }
     }
 }
 
@@ -471,153 +3018,428 @@ impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, i64> for AppData {
         event: <zwlr_layer_surface_v1::ZwlrLayerSurfaceV1 as Proxy>::Event,
         data: &i64,
         _connection: &wayland_client::Connection,
-        _queue_handle: &wayland_client::QueueHandle<Self>,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                debug!(
+                    "| Received zwlr_layer_surface_v1::Event::Configure for output {}",
+                    data
+                );
+                // acknowledge the Configure event
+                proxy.ack_configure(serial);
+                trace!("  size: {}x{}", width, height);
+                state.surface_configured(*data, serial);
+            }
+            zwlr_layer_surface_v1::Event::Closed => {
+                debug!(
+                    "| Received zwlr_layer_surface_v1::Event::Closed for output {}",
+                    data
+                );
+                proxy.destroy();
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<XdgWmBase, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        proxy: &XdgWmBase,
+        event: <XdgWmBase as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            debug!("| Received xdg_wm_base::Event::Ping");
+            // the compositor checks whether we're still responsive
+            proxy.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, i64> for AppData {
+    fn event(
+        state: &mut Self,
+        proxy: &XdgSurface,
+        event: <XdgSurface as Proxy>::Event,
+        data: &i64,
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            debug!(
+                "| Received xdg_surface::Event::Configure for output {}",
+                data
+            );
+            // sent after the toplevel configure, acknowledge it the same way as a layer surface configure
+            proxy.ack_configure(serial);
+            state.surface_configured(*data, serial);
+        }
+    }
+}
+
+impl Dispatch<XdgToplevel, i64> for AppData {
+    fn event(
+        state: &mut Self,
+        _proxy: &XdgToplevel,
+        event: <XdgToplevel as Proxy>::Event,
+        data: &i64,
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            xdg_toplevel::Event::Configure { width, height, .. } => {
+                debug!(
+                    "| Received xdg_toplevel::Event::Configure for output {}",
+                    data
+                );
+                // size of the fullscreen toplevel, applied with the xdg_surface configure
+                trace!("  size: {}x{}", width, height);
+            }
+            xdg_toplevel::Event::Close => {
+                debug!("| Received xdg_toplevel::Event::Close for output {}", data);
+                info!("> Window closed - exiting...");
+                state.exit = true;
+            }
+            _ => (),
+        }
+    }
+}
+
+// has no events
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrScreencopyManagerV1,
+        _event: <ZwlrScreencopyManagerV1 as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, i64> for AppData {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrScreencopyFrameV1,
+        event: <ZwlrScreencopyFrameV1 as Proxy>::Event,
+        data: &i64,
+        _connection: &wayland_client::Connection,
+        queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format,
+                width,
+                height,
+                stride,
+            } => {
+                debug!(
+                    "| Received zwlr_screencopy_frame_v1::Event::Buffer for output {}",
+                    data
+                );
+                // provides information about wl_shm buffer parameters that need to be used for this frame
+                // sent once after the frame is created if wl_shm buffers are supported
+                let format = match format.into_result() {
+                    Ok(format) => format,
+                    Err(e) => {
+                        error!("Unknown shm format offered for output {}: {}", data, e);
+                        state.capture_failed(*data, queue_handle);
+                        return;
+                    }
+                };
+                // the buffer is created once all buffer types are reported, i.e. on BufferDone
+                vec_insert(
+                    &mut state.shm_buffer_infos,
+                    *data,
+                    ShmBufferInfo {
+                        format,
+                        width,
+                        height,
+                        stride,
+                    },
+                );
+                // BufferDone was added in v3, before that wl_shm was the only buffer type
+                if proxy.version() < 3 {
+                    state.copy_screencopy_frame(*data, proxy, queue_handle);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => {
+                debug!(
+                    "| Received zwlr_screencopy_frame_v1::Event::BufferDone for output {}",
+                    data
+                );
+                // all buffer types are reported, proceed to create a buffer & send copy request
+                // after copy -> wait for Event::Ready
+                state.copy_screencopy_frame(*data, proxy, queue_handle);
+            }
+            zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                debug!(
+                    "| Received zwlr_screencopy_frame_v1::Event::Flags for output {}",
+                    data
+                );
+                // provides flags about the frame, sent once before Ready
+                let flags = match flags.into_result() {
+                    Ok(flags) => flags,
+                    Err(_) => zwlr_screencopy_frame_v1::Flags::empty(),
+                };
+                trace!("  frame flags: {:?}", flags);
+                vec_insert(&mut state.screencopy_flags, *data, flags);
+            }
+            zwlr_screencopy_frame_v1::Event::Damage {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                debug!(
+                    "| Received zwlr_screencopy_frame_v1::Event::Damage for output {}",
+                    data
+                );
+                // part of the frame that changed since the previous copy
+                state.frame_damage.entry(*data).or_default().push(Region {
+                    x: x as i32,
+                    y: y as i32,
+                    width: width as i32,
+                    height: height as i32,
+                });
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                debug!(
+                    "| Received zwlr_screencopy_frame_v1::Event::Ready for output {}",
+                    data
+                );
+                // copy done, frame is available for reading
+                state.check_frame_size(*data);
+                if !state.change_frame_ready(*data, queue_handle) {
+                    return;
+                }
+                state.captured_outputs.push(*data);
+                state.frames_ready += 1;
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                debug!(
+                    "| Received zwlr_screencopy_frame_v1::Event::Failed for output {}",
+                    data
+                );
+                error!("Failed to get a screencopyframe (output {})", data);
+                state.capture_failed(*data, queue_handle);
+            }
+            _ => (),
+        }
+    }
+}
+
+// has no events
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ExtOutputImageCaptureSourceManagerV1,
+        _event: <ExtOutputImageCaptureSourceManagerV1 as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// has no events
+impl Dispatch<ExtImageCaptureSourceV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ExtImageCaptureSourceV1,
+        _event: <ExtImageCaptureSourceV1 as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// has no events
+impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ExtImageCopyCaptureManagerV1,
+        _event: <ExtImageCopyCaptureManagerV1 as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, i64> for AppData {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtImageCopyCaptureSessionV1,
+        event: <ExtImageCopyCaptureSessionV1 as Proxy>::Event,
+        data: &i64,
+        _connection: &wayland_client::Connection,
+        queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
-            zwlr_layer_surface_v1::Event::Configure {
-                serial,
-                width: _,
-                height: _,
-            } => {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
                 debug!(
-                    "| Received zwlr_layer_surface_v1::Event::Configure for output {}",
+                    "| Received ext_image_copy_capture_session_v1::Event::BufferSize for output {}",
                     data
                 );
-                // acknowledge the Configure event
-                proxy.ack_configure(serial);
-
-                if state.configured_surfaces.contains_key(data) {
-                    debug!(
-                        "| Surface {} has already been configured, ignoring new Configure event",
-                        data
-                    );
+                // dimensions of the buffers that frames of this session have to be copied into
+                vec_insert(&mut state.buffer_sizes, *data, (width, height));
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format } => {
+                debug!(
+                    "| Received ext_image_copy_capture_session_v1::Event::ShmFormat for output {}",
+                    data
+                );
+                // one event per wl_shm format supported for this session
+                let Ok(format) = format.into_result() else {
+                    trace!("  ignoring unknown shm format");
                     return;
+                };
+                let formats = state.shm_formats.get_or_insert_with(HashMap::new);
+                formats.entry(*data).or_default().push(format);
+            }
+            ext_image_copy_capture_session_v1::Event::Done => {
+                debug!(
+                    "| Received ext_image_copy_capture_session_v1::Event::Done for output {}",
+                    data
+                );
+                // all buffer constraints are sent, a frame can be created
+                // constraints can be resent later on, we only ever need one frame per session
+                if let Some(frames) = &state.capture_frames {
+                    if frames.contains_key(data) {
+                        trace!("  frame already created, ignoring new buffer constraints");
+                        return;
+                    }
                 }
-
-                let Some(surfaces) = &state.surfaces else {
-                    error!("No WlSurface loaded");
+                let Some((width, height)) = state
+                    .buffer_sizes
+                    .as_ref()
+                    .and_then(|s| s.get(data).copied())
+                else {
+                    error!("No buffer size received for output {}", data);
+                    state.capture_failed(*data, queue_handle);
                     return;
                 };
-                let Some(buffers) = &state.buffers else {
-                    error!("No WlBuffers loaded");
+                let offered = match &state.shm_formats {
+                    Some(formats) => formats.get(data).cloned().unwrap_or_default(),
+                    None => Vec::new(),
+                };
+                let Some(format) = select_shm_format(&offered, &state.shm_supported_formats) else {
+                    error!(
+                        "None of the shm formats offered for output {} can be used: {:?}",
+                        data, offered
+                    );
+                    state.capture_failed(*data, queue_handle);
                     return;
                 };
-                let Some(transforms) = &state.transforms else {
-                    error!("No transforms loaded");
+                // ext-image-copy-capture doesn't dictate a stride, use tightly packed rows
+                let info = ShmBufferInfo {
+                    format,
+                    width,
+                    height,
+                    stride: width * shm_format_bpp(format).unwrap_or(4),
+                };
+                let Some(buffer) = state.create_shm_buffer(*data, info, queue_handle) else {
+                    state.capture_failed(*data, queue_handle);
                     return;
                 };
-                trace!("  committing to surface {} before attaching buffers", data);
-                surfaces[data].commit(); // commit before attaching any buffers
 
-                trace!("  attaching buffer to surface");
-                surfaces[data].attach(Some(&buffers[data]), 0, 0);
-                surfaces[data].set_buffer_scale(1);
-                surfaces[data].set_buffer_transform(transforms[data]);
-                surfaces[data].commit();
+                trace!("  copying frame to buffer");
+                // attach the buffer to a new frame & capture it, sends Ready when successful
+                let frame = proxy.create_frame(queue_handle, *data);
+                frame.attach_buffer(&buffer);
+                frame.damage_buffer(0, 0, width as i32, height as i32);
+                frame.capture();
 
-                state.configured_surfaces.insert(*data, serial);
+                vec_insert(&mut state.capture_frames, *data, frame);
             }
-            zwlr_layer_surface_v1::Event::Closed => {
+            ext_image_copy_capture_session_v1::Event::Stopped => {
                 debug!(
-                    "| Received zwlr_layer_surface_v1::Event::Closed for output {}",
+                    "| Received ext_image_copy_capture_session_v1::Event::Stopped for output {}",
                     data
                 );
-                proxy.destroy();
+                // the capture source is gone, no more frames can be captured
+                if state.captured_outputs.contains(data) {
+                    debug!("| Output {} was already captured, ignoring", data);
+                    return;
+                }
+                error!("Capture session stopped (output {})", data);
+                state.capture_failed(*data, queue_handle);
             }
             _ => (),
         }
     }
 }
 
-// has no events
-impl Dispatch<ZwlrScreencopyManagerV1, ()> for AppData {
-    fn event(
-        _state: &mut Self,
-        _proxy: &ZwlrScreencopyManagerV1,
-        _event: <ZwlrScreencopyManagerV1 as Proxy>::Event,
-        _data: &(),
-        _connection: &wayland_client::Connection,
-        _queue_handle: &wayland_client::QueueHandle<Self>,
-    ) {
-    }
-}
-
-impl Dispatch<ZwlrScreencopyFrameV1, i64> for AppData {
+impl Dispatch<ExtImageCopyCaptureFrameV1, i64> for AppData {
     fn event(
         state: &mut Self,
-        proxy: &ZwlrScreencopyFrameV1,
-        event: <ZwlrScreencopyFrameV1 as Proxy>::Event,
+        _proxy: &ExtImageCopyCaptureFrameV1,
+        event: <ExtImageCopyCaptureFrameV1 as Proxy>::Event,
         data: &i64,
         _connection: &wayland_client::Connection,
         queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
-            zwlr_screencopy_frame_v1::Event::Buffer {
-                format,
+            ext_image_copy_capture_frame_v1::Event::Damage {
+                x,
+                y,
                 width,
                 height,
-                stride,
             } => {
                 debug!(
-                    "| Received zwlr_screencopy_frame_v1::Event::Buffer for output {}",
+                    "| Received ext_image_copy_capture_frame_v1::Event::Damage for output {}",
                     data
                 );
-                // provides information about wl_shm buffer parameters that need to be used for this frame
-                // sent once after the frame is created if wl_shm buffers are supported
-                let Some(pools) = &state.shm_pools else {
-                    error!("Could not load WlShmPools");
-                    return;
-                };
-
-                trace!(
-                    "  creating buffer, width: {}, height: {}, stride: {}, format: {:?}",
+                // part of the frame that changed since the previous frame of the session
+                state.frame_damage.entry(*data).or_default().push(Region {
+                    x,
+                    y,
                     width,
                     height,
-                    stride,
-                    format
-                );
-                // catch reported buffer type & create buffer
-                let buffer: wl_buffer::WlBuffer = pools[data].create_buffer(
-                    0, // buffer can take up the whole pool -> offset 0
-                    width as i32,
-                    height as i32,
-                    stride as i32,
-                    format.into_result().expect("Unsupported format"),
-                    &queue_handle,
-                    (),
-                );
-                vec_insert(&mut state.buffers, *data, buffer);
+                });
             }
-            zwlr_screencopy_frame_v1::Event::BufferDone { .. } => {
+            ext_image_copy_capture_frame_v1::Event::Transform { transform } => {
                 debug!(
-                    "| Received zwlr_screencopy_frame_v1::Event::BufferDone for output {}",
+                    "| Received ext_image_copy_capture_frame_v1::Event::Transform for output {}",
                     data
                 );
-                // all buffer types are reported, proceed to send copy request
-                // after copy -> wait for Event::Ready
-                let Some(buffer) = &state.buffers else {
-                    error!("Could not load WlBuffers");
-                    return;
-                };
-                trace!("  copying frame to buffer");
-                // copy frame to buffer, sends Ready when successful
-                proxy.copy(&buffer[data]);
+                // transform of the buffer contents, which can differ from the one of the output
+                if let Ok(transform) = transform.into_result() {
+                    trace!("  transform: {}", transform_name(transform));
+                    vec_insert(&mut state.frame_transforms, *data, transform);
+                }
             }
-            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+            ext_image_copy_capture_frame_v1::Event::Ready => {
                 debug!(
-                    "| Received zwlr_screencopy_frame_v1::Event::Ready for output {}",
+                    "| Received ext_image_copy_capture_frame_v1::Event::Ready for output {}",
                     data
                 );
                 // copy done, frame is available for reading
+                state.check_frame_size(*data);
+                if !state.change_frame_ready(*data, queue_handle) {
+                    return;
+                }
+                state.captured_outputs.push(*data);
                 state.frames_ready += 1;
             }
-            zwlr_screencopy_frame_v1::Event::Failed => {
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
                 debug!(
-                    "| Received zwlr_screencopy_frame_v1::Event::Failed for output {}",
+                    "| Received ext_image_copy_capture_frame_v1::Event::Failed for output {}",
                     data
                 );
-                error!("Failed to get a screencopyframe (output {})", data);
-                state.exit = true;
+                error!("Failed to capture a frame (output {}): {:?}", data, reason);
+                state.capture_failed(*data, queue_handle);
             }
             _ => (),
         }
@@ -647,6 +3469,19 @@ impl Dispatch<ZxdgOutputV1, i64> for AppData {
         _queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                // describes the position of the output in the global compositor space
+                debug!(
+                    "| Received zxdg_output_v1::Event::LogicalPosition for output {}",
+                    data
+                );
+                let previous = state.positions.as_ref().and_then(|p| p.get(data).copied());
+                vec_insert(&mut state.positions, *data, (x, y));
+                // only the part of the output within the geometry depends on its position
+                if state.geometry.is_some() && previous.is_some_and(|p| p != (x, y)) {
+                    state.output_changed(*data);
+                }
+            }
             zxdg_output_v1::Event::LogicalSize { width, height } => {
                 // describes the size of the output in the global compositor space
                 debug!(
@@ -654,10 +3489,28 @@ impl Dispatch<ZxdgOutputV1, i64> for AppData {
                     data
                 );
 
-                // save the width & height of this output under the same key as this output's index in the vector
-                vec_insert(&mut state.widths, *data, width);
-                vec_insert(&mut state.heights, *data, height);
-                state.outputs_ready += 1;
+                state.set_logical_size(*data, width, height);
+            }
+            zxdg_output_v1::Event::Name { name } => {
+                // name of the output, only used if wl_output didn't send one
+                debug!("| Received zxdg_output_v1::Event::Name for output {}", data);
+                if !state.names.as_ref().is_some_and(|n| n.contains_key(data)) {
+                    vec_insert(&mut state.names, *data, name);
+                }
+            }
+            zxdg_output_v1::Event::Description { description } => {
+                // description of the output, only used if wl_output didn't send one
+                debug!(
+                    "| Received zxdg_output_v1::Event::Description for output {}",
+                    data
+                );
+                if !state
+                    .descriptions
+                    .as_ref()
+                    .is_some_and(|d| d.contains_key(data))
+                {
+                    vec_insert(&mut state.descriptions, *data, description);
+                }
             }
             _ => (),
         }
@@ -686,70 +3539,109 @@ impl Dispatch<WpFractionalScaleV1, i64> for AppData {
         _connection: &wayland_client::Connection,
         _queue_handle: &wayland_client::QueueHandle<Self>,
     ) {
-        match event {
-            wp_fractional_scale_v1::Event::PreferredScale { scale } => {
-                // notifies of a new preferred scale for this surface
-                debug!(
-                    "| Received wp_fractional_scale_v1::Event::PreferredScale for output {}",
-                    data
-                );
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            // notifies of a new preferred scale for this surface
+            debug!(
+                "| Received wp_fractional_scale_v1::Event::PreferredScale for output {}",
+                data
+            );
 
-                if let Some(scales) = &state.scales {
-                    // check if we already have scale data for this output
-                    if *data < scales.len() as i64 {
-                        // check if the scale has changed
-                        // otherwise we'll commit again a little further down, get the event again, commit, event, etc.
-                        if scales[data] == scale as i32 {
-                            trace!("  event contained same scale as previous event: {}", scale);
-                            return;
-                        } else {
-                            trace!("  event contained new scale: {}", scale);
-                        }
+            if let Some(scales) = &state.scales {
+                // check if we already have scale data for this output
+                if let Some(previous_scale) = scales.get(data) {
+                    // check if the scale has changed
+                    // otherwise we'll commit again a little further down, get the event again, commit, event, etc.
+                    if *previous_scale == scale as i32 {
+                        trace!("  event contained same scale as previous event: {}", scale);
+                        return;
+                    } else {
+                        trace!("  event contained new scale: {}", scale);
+                        state.output_changed(*data);
                     }
                 }
+            }
 
-                let Some(surfaces) = &state.surfaces else {
-                    error!("No WlSurface loaded");
-                    return;
-                };
-                let Some(layer_surfaces) = &state.layer_surfaces else {
-                    error!("No ZwlrLayerSurfaceV1 loaded");
-                    return;
-                };
-                let Some(viewports) = &state.viewports else {
-                    error!("Could not load WpViewPortV1s");
-                    return;
-                };
-                let Some(widths) = &state.widths else {
-                    error!("Could not load widths");
-                    return;
-                };
-                let Some(heights) = &state.heights else {
-                    error!("Could not load heights");
-                    return;
-                };
-                trace!(
-                    "  setting scale to {}/120 = {}, width: {} height: {}",
-                    scale,
-                    scale as f64 / 120.0,
-                    widths[data],
-                    heights[data]
-                );
+            if state.refreeze_outputs.contains(data) {
+                // the viewport of the new frame is set once it's presented, the attached buffer may not fit it
+                vec_insert(&mut state.scales, *data, scale as i32);
+                return;
+            }
 
-                // set source & destination rectangle
-                viewports[data].set_source(-1.0, -1.0, -1.0, -1.0);
-                viewports[data].set_destination(widths[data] as i32, heights[data] as i32);
-                // update layer surface size every time the preferred scale changes
-                layer_surfaces[data].set_size(widths[data] as u32, heights[data] as u32);
-                surfaces[data].commit();
+            let Some(surfaces) = &state.surfaces else {
+                error!("No WlSurface loaded");
+                return;
+            };
+            let Some(viewports) = &state.viewports else {
+                error!("Could not load WpViewPortV1s");
+                return;
+            };
+            let Some(regions) = &state.regions else {
+                error!("Could not load regions");
+                return;
+            };
+            let region = regions[data];
+            trace!(
+                "  setting scale to {}/120 = {}, width: {} height: {}",
+                scale,
+                scale as f64 / 120.0,
+                region.width,
+                region.height
+            );
 
-                vec_insert(&mut state.scales, *data, scale as i32)
+            // set source & destination rectangle
+            let (x, y, width, height) = state.viewport_source(*data);
+            viewports[data].set_source(x, y, width, height);
+            viewports[data].set_destination(region.width, region.height);
+            // update layer surface size every time the preferred scale changes, toplevels are sized by the compositor
+            if let Some(layer_surface) = state.layer_surfaces.as_ref().and_then(|l| l.get(data)) {
+                layer_surface.set_size(region.width as u32, region.height as u32);
             }
-            _ => {}
+            surfaces[data].commit();
+
+            vec_insert(&mut state.scales, *data, scale as i32)
         }
     }
 }
 
+// has no events
+impl Dispatch<wl_region::WlRegion, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_region::WlRegion,
+        _event: <wl_region::WlRegion as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// has no events
+impl Dispatch<wl_subcompositor::WlSubcompositor, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_subcompositor::WlSubcompositor,
+        _event: <wl_subcompositor::WlSubcompositor as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
+// has no events
+impl Dispatch<wl_subsurface::WlSubsurface, ()> for AppData {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_subsurface::WlSubsurface,
+        _event: <wl_subsurface::WlSubsurface as Proxy>::Event,
+        _data: &(),
+        _connection: &wayland_client::Connection,
+        _queue_handle: &wayland_client::QueueHandle<Self>,
+    ) {
+    }
+}
+
 // has no events
 impl Dispatch<WpViewporter, ()> for AppData {
     fn event(
@@ -780,228 +3672,808 @@ struct ScreenFreezer {
     event_queue: EventQueue<AppData>,
     queue_handle: QueueHandle<AppData>,
     state: AppData,
+    // signal handlers write to this socket to wake up the event loop
+    signal_pipe: UnixStream,
+    refreeze_signal: Arc<AtomicBool>,
+    replay_signal: Arc<AtomicBool>,
+    child_signal: Arc<AtomicBool>,
+    // accepts commands while recording a replay
+    replay_socket: Option<(UnixListener, PathBuf)>,
+    // clients of the replay socket & the part of their command that was received so far
+    replay_clients: Vec<(UnixStream, Vec<u8>)>,
+    // print the picked output as JSON
+    json: bool,
 }
 
 impl ScreenFreezer {
-    fn new(
-        hide_cursor: bool,
-        before_cmd: String,
-        after_cmd: String,
-        before_timeout: u64,
-        after_timeout: u64,
-    ) -> Result<Self, Box<dyn Error>> {
+    fn new(args: Args) -> Result<Self, Box<dyn Error>> {
         let connection = Connection::connect_to_env().unwrap();
         let mut event_queue = connection.new_event_queue();
         let queue_handle = event_queue.handle();
         let display = connection.display();
         let _registry = display.get_registry(&queue_handle, ());
-        let mut state = AppData::default();
-        state.hide_cursor = hide_cursor;
-        state.before_cmd = before_cmd;
-        state.after_cmd = after_cmd;
-        state.before_timeout = before_timeout;
-        state.after_timeout = after_timeout;
+        let mut state = AppData {
+            hide_cursor: args.hide_cursor,
+            pick_output: args.pick_output,
+            layer: args.layer,
+            namespace: args.namespace,
+            // input that passes through the freeze shouldn't be grabbed by the keyboard either
+            keyboard_mode: if args.passthrough {
+                KeyboardMode::None
+            } else {
+                args.keyboard
+            },
+            passthrough: args.passthrough,
+            mirror: args.mirror,
+            mirror_scaling: args.mirror_scaling,
+            before_cmd: args.before_freeze_cmd,
+            after_cmd: args.after_freeze_cmd,
+            before_timeout: args.before_freeze_timeout,
+            after_timeout: args.after_freeze_timeout,
+            capture_backend: args.capture_backend,
+            capture_retries: args.capture_retries,
+            capture_retry_delay: args.capture_retry_delay,
+            on_capture_failure: args.on_capture_failure,
+            refreeze_uncover: args.refreeze_uncover,
+            geometry: args.geometry,
+            include_outputs: args.outputs,
+            // only the focused output, which the compositor has to tell us about
+            focused_output: if args.focused_output {
+                Some(focused_output::focused_output()?)
+            } else {
+                None
+            },
+            exclude_outputs: args.exclude_outputs,
+            freeze_on_change: args.freeze_on_change,
+            change_geometry: args.change_geometry,
+            burst: args.burst,
+            burst_interval: args.burst_interval,
+            replay: args.replay,
+            replay_interval: args.replay_interval,
+            ..Default::default()
+        };
 
         event_queue.roundtrip(&mut state).unwrap();
         info!("> Received all globals");
 
         state.context = Some(xkb::Context::new(xkb::CONTEXT_NO_FLAGS));
 
+        // SIGUSR1 refreezes the screen, SIGUSR2 freezes to the replay frames, SIGCHLD checks whether the after-freeze command exited
+        let (signal_pipe, signal_writer) = UnixStream::pair()?;
+        signal_pipe.set_nonblocking(true)?;
+        signal_writer.set_nonblocking(true)?;
+        let refreeze_signal = Arc::new(AtomicBool::new(false));
+        let replay_signal = Arc::new(AtomicBool::new(false));
+        let child_signal = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(SIGUSR1, Arc::clone(&refreeze_signal))?;
+        signal_hook::flag::register(SIGUSR2, Arc::clone(&replay_signal))?;
+        signal_hook::flag::register(SIGCHLD, Arc::clone(&child_signal))?;
+        signal_hook::low_level::pipe::register(SIGUSR1, signal_writer.try_clone()?)?;
+        signal_hook::low_level::pipe::register(SIGUSR2, signal_writer.try_clone()?)?;
+        signal_hook::low_level::pipe::register(SIGCHLD, signal_writer)?;
+
+        let replay_socket = match args.replay_socket {
+            Some(path) => {
+                let listener = bind_socket(&path)?;
+                listener.set_nonblocking(true)?;
+                Some((listener, path))
+            }
+            None => None,
+        };
+
         Ok(Self {
             event_queue,
             queue_handle,
             state,
+            signal_pipe,
+            refreeze_signal,
+            replay_signal,
+            child_signal,
+            replay_socket,
+            replay_clients: Vec::new(),
+            json: args.json,
         })
     }
-    pub fn freeze(&mut self) -> Result<(), Box<dyn Error>> {
+    // blocks until there are wayland events, signals or commands to handle, or until the timeout expires
+    fn dispatch(&mut self, timeout: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        // wake up in time to retry failed captures & to capture outputs that weren't shown uncovered
+        let deadline = self
+            .state
+            .capture_retry_deadlines
+            .values()
+            .chain(self.state.uncover_deadlines.values())
+            .min();
+        let timeout = match deadline {
+            Some(deadline) => {
+                let until_retry = deadline.saturating_duration_since(Instant::now());
+                Some(timeout.map_or(until_retry, |t| t.min(until_retry)))
+            }
+            None => timeout,
+        };
+        self.event_queue.flush()?;
+        if let Some(guard) = self.event_queue.prepare_read() {
+            let connection_fd = guard.connection_fd();
+            let mut fds = vec![
+                PollFd::new(&connection_fd, PollFlags::IN),
+                PollFd::new(&self.signal_pipe, PollFlags::IN),
+            ];
+            if let Some((listener, _)) = &self.replay_socket {
+                fds.push(PollFd::new(listener, PollFlags::IN));
+            }
+            for (stream, _) in &self.replay_clients {
+                fds.push(PollFd::new(stream, PollFlags::IN));
+            }
+            let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+            match poll(&mut fds, timeout) {
+                Ok(_) | Err(Errno::INTR) => (),
+                Err(e) => return Err(e.into()),
+            }
+            let wayland_ready = !fds[0].revents().is_empty();
+            let signal_ready = !fds[1].revents().is_empty();
+            let socket_ready = fds[2..].iter().any(|fd| !fd.revents().is_empty());
+            drop(fds);
+            if socket_ready {
+                self.read_replay_commands();
+            }
+            if wayland_ready {
+                guard.read()?;
+            }
+            if signal_ready {
+                // drain the socket, the flags tell which signals were received
+                let mut buf = [0u8; 64];
+                while let Ok(n) = self.signal_pipe.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        if self.refreeze_signal.swap(false, Ordering::Relaxed) {
+            info!("> Received SIGUSR1 - refreezing...");
+            self.state.refreeze_requested = true;
+        }
+        if self.replay_signal.swap(false, Ordering::Relaxed) && self.state.replay.is_some() {
+            info!("> Received SIGUSR2 - replaying...");
+            self.state.replay_requested = true;
+        }
+        if self.child_signal.swap(false, Ordering::Relaxed) {
+            self.check_after_child();
+        }
+        self.event_queue.dispatch_pending(&mut self.state)?;
+        self.state.retry_captures(&self.queue_handle);
+        self.state.capture_uncovered_outputs(&self.queue_handle);
+        Ok(())
+    }
+    // accepts clients of the replay socket & handles the commands they sent, without waiting for the rest of them
+    fn read_replay_commands(&mut self) {
+        let Some((listener, _)) = &self.replay_socket else {
+            return;
+        };
+        while let Ok((stream, _)) = listener.accept() {
+            // a client that never sends anything can't block the recording
+            if stream.set_nonblocking(true).is_ok() {
+                self.replay_clients.push((stream, Vec::new()));
+            }
+        }
+        let mut commands = Vec::new();
+        self.replay_clients.retain_mut(|(stream, pending)| {
+            let (received, open) = read_socket_commands(stream, pending);
+            commands.extend(received);
+            open
+        });
+        for command in commands {
+            match command.as_str() {
+                // e.g. another instance checking whether the socket is in use
+                "" => (),
+                "freeze" => {
+                    info!("> Received freeze command - replaying...");
+                    self.state.replay_requested = true;
+                }
+                command => warn!("Unknown command on replay socket: {:?}", command),
+            }
+        }
+    }
+    // keeps capturing every frozen output into a ring of frames until a replay is requested
+    fn record_replay(&mut self, replay: u64) -> Result<(), Box<dyn Error>> {
+        let interval = Duration::from_millis(self.state.replay_interval.max(1));
+        let capacity = (replay / self.state.replay_interval.max(1)) as usize + 1;
+        info!(
+            "> Recording the last {}ms of {} output(s), {} frames each",
+            replay,
+            self.state.frozen_outputs().len(),
+            capacity
+        );
+        let mut round_started = Instant::now();
+        let mut round_done = false;
+        self.state.capture_round(&self.queue_handle)?;
+        loop {
+            if self.state.exit {
+                return Err("Could not capture every output".into());
+            }
+            if self.state.replay_requested {
+                break;
+            }
+            if !round_done && self.state.frames_ready == self.state.frozen_outputs().len() as i32 {
+                self.state.record_replay_frames(capacity);
+                round_done = true;
+            }
+            if !round_done {
+                self.dispatch(None)?;
+                continue;
+            }
+            let elapsed = round_started.elapsed();
+            if elapsed >= interval {
+                round_started = Instant::now();
+                round_done = false;
+                self.state.capture_round(&self.queue_handle)?;
+                continue;
+            }
+            self.dispatch(Some(interval - elapsed))?;
+        }
+        // throw away the frames that are still being captured
+        for output in self.state.frozen_outputs() {
+            self.state.reset_capture(output);
+        }
+        self.replay_clients.clear();
+        if let Some((_, path)) = self.replay_socket.take() {
+            fs::remove_file(path)?;
+        }
+        self.state.select_replay_frames();
+        if self.state.frozen_outputs().is_empty() {
+            return Err("Nothing was recorded".into());
+        }
+        Ok(())
+    }
+    fn resolve_capture_backend(&self) -> Result<CaptureBackend, Box<dyn Error>> {
+        let ext_supported = self.state.capture_source_manager.is_some()
+            && self.state.copy_capture_manager.is_some();
+        let wlr_supported = self.state.screencopy_manager.is_some();
+        match self.state.capture_backend {
+            CaptureBackend::Auto if ext_supported => Ok(CaptureBackend::ExtImageCopyCapture),
+            CaptureBackend::Auto if wlr_supported => Ok(CaptureBackend::WlrScreencopy),
+            CaptureBackend::ExtImageCopyCapture if ext_supported => {
+                Ok(CaptureBackend::ExtImageCopyCapture)
+            }
+            CaptureBackend::WlrScreencopy if wlr_supported => Ok(CaptureBackend::WlrScreencopy),
+            CaptureBackend::Auto => Err(
+                "Compositor supports neither ext-image-copy-capture-v1 nor wlr-screencopy-unstable-v1"
+                    .into(),
+            ),
+            CaptureBackend::ExtImageCopyCapture => {
+                Err("Compositor does not support ext-image-copy-capture-v1".into())
+            }
+            CaptureBackend::WlrScreencopy => {
+                Err("Compositor does not support wlr-screencopy-unstable-v1".into())
+            }
+        }
+    }
+    fn select_regions(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(outputs) = &self.state.outputs else {
+            return Ok(());
+        };
+        if self.state.widths.is_none() || self.state.heights.is_none() {
+            return Err("No logical output sizes received".into());
+        }
+        let mut keys: Vec<i64> = outputs.keys().copied().collect();
+        keys.sort();
+        let mut regions = HashMap::new();
+        let mut selected = 0;
+        for i in keys {
+            if !self.state.output_selected(i) {
+                debug!("| Output {} is not selected, skipping", i);
+                continue;
+            }
+            selected += 1;
+            let Some(region) = self.state.output_region(i) else {
+                debug!("| Output {} is outside of the geometry, skipping", i);
+                continue;
+            };
+            regions.insert(i, region);
+        }
+        if selected == 0 {
+            return Err("No output matches --output & --exclude-output".into());
+        }
+        if regions.is_empty() {
+            return Err("Geometry does not intersect any selected output".into());
+        }
+        self.state.regions = Some(regions);
+        Ok(())
+    }
+    fn run_after_freeze_cmd(&mut self) {
+        if self.state.after_cmd.is_empty() {
+            return;
+        }
+        sleep(Duration::from_millis(self.state.after_timeout));
+        info!("> Running after-freeze commands: {}", &self.state.after_cmd);
+        self.state.after_child = Some(
+            Command::new("sh")
+                .arg("-c")
+                .arg(&self.state.after_cmd)
+                .spawn()
+                .expect("Failed to run after-freeze commands"),
+        );
+    }
+    // without a keyboard, there's no key to exit with, so the after-freeze command exiting ends the freeze
+    fn check_after_child(&mut self) {
+        if self.state.keyboard_mode != KeyboardMode::None {
+            return;
+        }
+        let Some(child) = &mut self.state.after_child else {
+            return;
+        };
+        if let Ok(Some(status)) = child.try_wait() {
+            info!("> After-freeze commands exited ({}) - exiting...", status);
+            self.state.after_child = None;
+            self.state.exit = true;
+        }
+    }
+    // waits until the size & position of every output is known
+    fn wait_for_outputs(&mut self) -> Result<(), Box<dyn Error>> {
         // check self.state.outputs
         match &self.state.outputs {
-            // if the vector exists -> we're good, at least 1 output was found & bound to
-            Some(vec) => {
-                info!("> Bound to {} output(s)", vec.len());
-                self.state.output_count = vec.len() as i32;
+            // if the map exists -> we're good, at least 1 output was found & bound to
+            Some(outputs) => {
+                info!("> Bound to {} output(s)", outputs.len());
+                self.state.output_count = outputs.len() as i32;
             }
             None => {
-                // no vector -> no outputs found
-                error!("No outputs found - exiting...");
-                self.state.exit = true;
+                // no map -> no outputs found
+                return Err("No outputs found".into());
             }
         }
 
-        self.state.outputs_ready = 0;
-        self.state.frames_ready = 0;
-
-        self.event_queue.blocking_dispatch(&mut self.state).unwrap();
-
-        loop {
+        while self.state.outputs_ready != self.state.output_count {
             self.event_queue.blocking_dispatch(&mut self.state).unwrap();
-            if self.state.outputs_ready == self.state.output_count as i32 {
-                break;
+        }
+        Ok(())
+    }
+    // prints the layout of every output
+    pub fn list_outputs(&mut self, json: bool) -> Result<(), Box<dyn Error>> {
+        self.wait_for_outputs()?;
+        let layout = self.state.output_layout();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&layout)?);
+        } else {
+            for output in layout {
+                println!("{}", output);
             }
         }
+        Ok(())
+    }
+    pub fn freeze(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.state.keyboard_mode == KeyboardMode::None && self.state.burst > 1 {
+            // the frames of a burst are picked with the arrow keys & enter
+            return Err("Picking a frame of a burst requires a keyboard".into());
+        }
+        if self.state.keyboard_mode == KeyboardMode::None && self.state.pick_output {
+            // outputs are picked by pressing their number as well
+            return Err("Picking an output requires a keyboard".into());
+        }
+        self.wait_for_outputs()?;
 
-        let Some(outputs) = &self.state.outputs else {
-            return Ok(());
-        };
+        // pick the protocol used to copy the outputs
+        self.state.capture_backend = self.resolve_capture_backend()?;
+        info!("> Using capture backend {:?}", self.state.capture_backend);
+        if self.state.freeze_on_change
+            && self.state.capture_backend == CaptureBackend::WlrScreencopy
+        {
+            if let Some((screencopy_manager, _)) = &self.state.screencopy_manager {
+                if screencopy_manager.version() < 2 {
+                    return Err(
+                        "Freezing on change requires wlr-screencopy-unstable-v1 v2 or newer".into(),
+                    );
+                }
+            }
+        }
 
-        // create screencopy frame, copy screen contents to buffer
-        info!("> Processing {} output(s)", outputs.len());
-        for i in 0..outputs.len() as i64 {
-            trace!("  processing output {}", i);
+        if self.state.layer_shell.is_none() {
+            if self.state.wm_base.is_none() {
+                return Err("Neither ZwlrLayerShellV1 nor XdgWmBase loaded".into());
+            }
+            // toplevels can't be placed on top of a region
+            if self.state.geometry.is_some() {
+                return Err("Freezing a region requires wlr-layer-shell".into());
+            }
+            // a fullscreen toplevel takes keyboard focus, keys wouldn't reach the windows underneath
+            if self.state.passthrough {
+                return Err("--passthrough requires wlr-layer-shell".into());
+            }
+            warn!("No ZwlrLayerShellV1 loaded, falling back to fullscreen toplevels");
+        } else if self.state.keyboard_mode == KeyboardMode::OnDemand
+            && self
+                .state
+                .layer_shell
+                .as_ref()
+                .is_some_and(|(layer_shell, _)| layer_shell.version() < 4)
+        {
+            // on_demand keyboard interactivity was added in v4
+            warn!("ZwlrLayerShellV1 is older than v4, which doesn't support --keyboard on-demand, falling back to exclusive");
+            self.state.keyboard_mode = KeyboardMode::Exclusive;
+        }
+        if self.state.viewporter.is_none() {
+            // ext-image-copy-capture copies whole outputs, which have to be cropped to the region
+            if self.state.geometry.is_some()
+                && self.state.capture_backend == CaptureBackend::ExtImageCopyCapture
+            {
+                return Err(
+                    "Freezing a region with ext-image-copy-capture requires wp_viewporter".into(),
+                );
+            }
+            warn!("No WpViewporter loaded, falling back to integer scaling");
+        } else if self.state.fs_manager.is_none() {
+            warn!("No WpFractionalScaleManagerV1 loaded, frames are scaled to the logical size");
+        }
 
-            let Some(outputs) = &self.state.outputs else {
-                error!("Could not load WlOutputs");
-                return Ok(());
-            };
-            let Some((screencopy_manager, _)) = &self.state.screencopy_manager else {
-                error!("No ZwlrScreencopyManagerV1 loaded");
-                return Ok(());
-            };
-            let Some((shm, _)) = &self.state.shm else {
-                error!("No WlShm loaded");
-                return Ok(());
-            };
-            let Some(phys_widths) = &self.state.phys_widths else {
-                error!("Could not load widths");
-                return Ok(());
-            };
-            let Some(phys_heights) = &self.state.phys_heights else {
-                error!("Could not load heights");
-                return Ok(());
+        // work out which part of each output has to be frozen
+        self.select_regions()?;
+        let frozen_outputs = self.state.frozen_outputs();
+        if let Some(mirror) = &self.state.mirror {
+            if self.state.viewporter.is_none() || self.state.subcompositor.is_none() {
+                return Err("Mirroring requires wp_viewporter & wl_subcompositor".into());
+            }
+            let source = frozen_outputs.iter().copied().find(|o| {
+                let name = self.state.names.as_ref().and_then(|n| n.get(o));
+                let description = self.state.descriptions.as_ref().and_then(|d| d.get(o));
+                name.is_some_and(|name| glob_match(mirror, name))
+                    || description.is_some_and(|description| glob_match(mirror, description))
+            });
+            let Some(source) = source else {
+                return Err("No frozen output matches --mirror".into());
             };
-            // create pool
-            let tmp = tempfile().ok().expect("Unable to create tempfile");
-            let pool_size = phys_heights[&i] * phys_widths[&i] * 4; // height * width * 4 -> total size of the pool
-            tmp.set_len(pool_size as u64).unwrap();
-            let pool: wl_shm_pool::WlShmPool =
-                wl_shm::WlShm::create_pool(&shm, tmp.as_fd(), pool_size, &self.queue_handle, ());
-
-            trace!("  capturing output {}", i);
-            // create screencopyframe from output
-            let screencopy_frame = screencopy_manager.capture_output(
-                !self.state.hide_cursor as i32,
-                &outputs[i as usize],
-                &self.queue_handle,
-                i,
-            );
-            vec_insert(&mut self.state.screencopy_frames, i, screencopy_frame);
-            vec_insert(&mut self.state.shm_pools, i, pool);
+            info!("> Mirroring output {} onto the other outputs", source);
+            self.state.mirror_source = Some(source);
+        }
+
+        // buffers are allocated once the compositor has sent the buffer parameters of a frame
+        if self.state.shm.is_none() {
+            return Err("No WlShm loaded".into());
+        }
+
+        // create screencopy frame, copy screen contents to buffer
+        if let Some(replay) = self.state.replay {
+            self.record_replay(replay)?;
+        } else {
+            info!("> Processing {} output(s)", frozen_outputs.len());
+            for &i in &frozen_outputs {
+                trace!("  processing output {}", i);
+                if self.state.is_mirror_target(i) {
+                    // black until the mirrored frame is put on top
+                    if !self.state.cover_output(i, &self.queue_handle) {
+                        return Err("Could not cover output".into());
+                    }
+                    continue;
+                }
+                self.state.capture_output(i, &self.queue_handle)?;
+            }
+            info!("> Processed {} output(s)", frozen_outputs.len());
         }
-        info!("> Processed {} output(s)", outputs.len());
 
         // wait for all frames to be copied & run before-freeze commands
+        let mut bursts_captured = 1;
+        // when the next frame of the burst is captured
+        let mut next_burst: Option<Instant> = None;
         loop {
-            self.event_queue.blocking_dispatch(&mut self.state).unwrap();
-            if self.state.frames_ready == self.state.output_count as i32 {
-                if &self.state.before_cmd != "" {
+            if self.state.exit {
+                return Err("Could not capture every output".into());
+            }
+            if let Some(deadline) = next_burst {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    // capture the next frame of the burst
+                    next_burst = None;
+                    self.state.capture_round(&self.queue_handle)?;
+                    bursts_captured += 1;
+                } else {
+                    // keep handling events until it's time for the next frame
+                    self.dispatch(Some(remaining))?;
+                }
+                continue;
+            }
+            // outputs that failed to be captured may have been skipped in the meantime
+            if self.state.frames_ready == self.state.frozen_outputs().len() as i32 {
+                if self.state.burst > 1 {
+                    self.state.stash_burst_frames();
+                    if bursts_captured < self.state.burst {
+                        next_burst =
+                            Some(Instant::now() + Duration::from_millis(self.state.burst_interval));
+                        continue;
+                    }
+                    info!("> Captured {} frames per output", bursts_captured);
+                    for i in self.state.frozen_outputs() {
+                        self.state.load_burst_frame(i);
+                    }
+                }
+                if !self.state.before_cmd.is_empty() {
                     info!(
                         "> Running before-freeze commands: {}",
                         &self.state.before_cmd
                     );
-                    Command::new("sh")
+                    let mut child = Command::new("sh")
                         .arg("-c")
                         .arg(&self.state.before_cmd)
                         .spawn()
                         .expect("Failed to run before-freeze commands");
+                    // nothing waits for the before-freeze commands, reap them so they don't linger as zombies
+                    thread::spawn(move || child.wait());
                     sleep(Duration::from_millis(self.state.before_timeout));
                 }
                 break;
             }
+            // failed captures are retried from here as well
+            self.dispatch(None)?;
         }
 
-        let Some(outputs) = &self.state.outputs else {
-            return Ok(());
-        };
+        let mut frozen_outputs = self.state.frozen_outputs();
+        if frozen_outputs.is_empty() {
+            return Err("Could not capture any output".into());
+        }
 
         // create & configure layer surface, attach buffer to it, fractional scaling & some cleanup
-        info!("> Creating {} layer surface(s)", outputs.len());
-        for i in 0..outputs.len() as i64 {
-            let Some(surfaces) = &self.state.surfaces else {
-                error!("No WlSurface loaded");
-                return Ok(());
-            };
-            let Some((layer_shell, _)) = &self.state.layer_shell else {
-                error!("No ZwlrLayerShellV1 loaded");
-                return Ok(());
-            };
-            let Some(outputs) = &self.state.outputs else {
-                return Ok(());
-            };
-            let output = &outputs[i as usize];
-
-            trace!("  creating layer surface {}", i);
-            // create a layer surface for the current output & its surface
-            let ls = zwlr_layer_shell_v1::ZwlrLayerShellV1::get_layer_surface(
-                layer_shell,
-                &surfaces[&i],
-                Some(&output),
-                Layer::Overlay,
-                "wayfreeze".to_string(),
-                &self.queue_handle,
-                i,
-            );
+        info!("> Creating {} layer surface(s)", frozen_outputs.len());
+        for &i in &frozen_outputs {
+            self.create_freeze_surface(i)?;
+        }
 
-            // configure layer surface
-            ls.set_anchor(Anchor::Top | Anchor::Right | Anchor::Bottom | Anchor::Left);
-            ls.set_exclusive_zone(-1); // extend surface to anchored edges
-            ls.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        while !frozen_outputs
+            .iter()
+            .all(|i| self.state.configured_surfaces.contains_key(i))
+        {
+            self.event_queue.blocking_dispatch(&mut self.state).unwrap();
+            // outputs that are unplugged in the meantime won't be configured
+            frozen_outputs.retain(|i| {
+                self.state
+                    .regions
+                    .as_ref()
+                    .is_some_and(|r| r.contains_key(i))
+            });
+            if frozen_outputs.is_empty() {
+                return Err("Every frozen output was removed".into());
+            }
+        }
+        self.state.frozen = true;
+        info!("> Screen frozen");
+        if self.state.pick_output {
+            self.state.show_output_hints(&self.queue_handle);
+            info!("> Click an output or press its number to pick it");
+        }
 
-            vec_insert(&mut self.state.layer_surfaces, i, ls);
+        // with a burst, the after-freeze commands run once a frame has been picked
+        if self.state.burst_frames.is_empty() {
+            self.run_after_freeze_cmd();
+        } else {
+            info!("> Use the arrow keys to step through the burst, press enter to pick a frame");
         }
 
-        // wl_layer_surfaces created, let's do wl_surfaces next
-        for i in 0..outputs.len() as i64 {
-            let Some(surfaces) = &self.state.surfaces else {
-                error!("No WlSurface loaded");
-                return Ok(());
+        loop {
+            self.dispatch(None)?;
+            if self.state.exit {
+                // nothing was picked
+                std::process::exit(if self.state.pick_output { 1 } else { 0 });
+            }
+            if let Some(output) = self.state.picked_output {
+                let info = self.state.output_info(output);
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&info)?);
+                } else {
+                    // the name, followed by the geometry in the format slurp prints
+                    println!(
+                        "{} {},{} {}x{}",
+                        info.name.as_deref().unwrap_or("(unnamed)"),
+                        info.x,
+                        info.y,
+                        info.width,
+                        info.height
+                    );
+                }
+                std::process::exit(0);
+            }
+            if self.state.frame_picked {
+                self.state.frame_picked = false;
+                self.run_after_freeze_cmd();
+            }
+            // freeze outputs that were plugged in while frozen
+            for output in std::mem::take(&mut self.state.hotplugged_outputs) {
+                self.freeze_hotplugged_output(output);
+            }
+            for output in self.state.pending_outputs.clone() {
+                if self.state.captured_outputs.contains(&output) {
+                    self.state.pending_outputs.retain(|o| *o != output);
+                    self.create_freeze_surface(output)?;
+                    if self.state.pick_output {
+                        // the outputs that were there already keep their hints
+                        self.state.show_output_hints(&self.queue_handle);
+                    }
+                } else if !self
+                    .state
+                    .regions
+                    .as_ref()
+                    .is_some_and(|r| r.contains_key(&output))
+                {
+                    // capturing it failed
+                    self.state.pending_outputs.retain(|o| *o != output);
+                }
+            }
+            if self.state.refreeze_requested {
+                self.state.refreeze_requested = false;
+                self.state.refreeze(&self.queue_handle);
+            }
+            if !self.state.refreezing && !self.state.changed_outputs.is_empty() {
+                self.state.follow_output_changes(&self.queue_handle);
+            }
+            if self.state.refreezing
+                && self
+                    .state
+                    .refreeze_outputs
+                    .iter()
+                    .all(|o| self.state.captured_outputs.contains(o))
+            {
+                self.state.finish_refreeze(&self.queue_handle);
+            }
+        }
+    }
+    // captures an output that was plugged in while frozen, its layer surface is created once it's captured
+    fn freeze_hotplugged_output(&mut self, output: i64) {
+        if !self.state.output_selected(output) {
+            debug!("| Output {} is not selected, leaving it unfrozen", output);
+            return;
+        }
+        let Some(region) = self.state.output_region(output) else {
+            debug!(
+                "| Output {} is outside of the geometry, leaving it unfrozen",
+                output
+            );
+            return;
+        };
+        info!("> Freezing output {} that was plugged in", output);
+        vec_insert(&mut self.state.regions, output, region);
+        if self.state.is_mirror_target(output) {
+            if self.state.cover_output(output, &self.queue_handle) {
+                self.state.pending_outputs.push(output);
+            } else {
+                vec_remove(&mut self.state.regions, &output);
+            }
+            return;
+        }
+        if let Err(e) = self.state.capture_output(output, &self.queue_handle) {
+            error!("Could not capture output {}: {}", output, e);
+            vec_remove(&mut self.state.regions, &output);
+            return;
+        }
+        self.state.pending_outputs.push(output);
+    }
+    // gives the surface of an output a role & sets up its scaling, the frame is attached on Configure
+    fn create_freeze_surface(&mut self, i: i64) -> Result<(), Box<dyn Error>> {
+        if self.state.layer_shell.is_some() {
+            self.create_layer_surface(i)?;
+        } else {
+            self.create_toplevel(i)?;
+        }
+        let Some(surfaces) = &self.state.surfaces else {
+            return Err("No WlSurface loaded".into());
+        };
+        if self.state.passthrough {
+            let Some((compositor, _)) = &self.state.compositor else {
+                return Err("No WlCompositor loaded".into());
             };
+            // an empty input region lets clicks & scrolling through to the windows underneath
+            let input_region = compositor.create_region(&self.queue_handle, ());
+            surfaces[&i].set_input_region(Some(&input_region));
+            input_region.destroy();
+        }
+        // role object created, let's do the wl_surface next
+        surfaces[&i].commit();
 
-            surfaces[&i].commit();
-
-            let Some((viewporter, _)) = &self.state.viewporter else {
-                error!("No WpViewPorter loaded");
-                return Ok(());
-            };
-            let Some((fs_manager, _)) = &self.state.fs_manager else {
-                error!("No WpFractionalScaleManagerV1 loaded");
-                return Ok(());
-            };
-            // instantiates an interface extension for the wl_surface to crop & scale its content
-            vec_insert(
-                &mut self.state.viewports,
-                i,
-                viewporter.get_viewport(&surfaces[&i], &self.queue_handle, ()),
-            );
+        // without a viewport, frames are presented at an integer scale
+        let Some((viewporter, _)) = &self.state.viewporter else {
+            return Ok(());
+        };
+        // instantiates an interface extension for the wl_surface to crop & scale its content
+        let viewport = viewporter.get_viewport(&surfaces[&i], &self.queue_handle, ());
+        if let Some(region) = self.state.regions.as_ref().and_then(|r| r.get(&i)) {
+            viewport.set_destination(region.width, region.height);
+        }
+        if let Some((fs_manager, _)) = &self.state.fs_manager {
             // create add-on object for the surface so that compositor can request fractional scales, will send preferred_scale event
-            fs_manager.get_fractional_scale(&surfaces[&i], &self.queue_handle, i);
+            let fractional_scale =
+                fs_manager.get_fractional_scale(&surfaces[&i], &self.queue_handle, i);
+            vec_insert(&mut self.state.fractional_scales, i, fractional_scale);
         }
+        vec_insert(&mut self.state.viewports, i, viewport);
 
-        while self.state.configured_surfaces.len() != self.state.output_count as usize {
-            self.event_queue.blocking_dispatch(&mut self.state).unwrap();
+        if self.state.is_mirror_target(i) {
+            let (Some((compositor, _)), Some((subcompositor, _))) =
+                (&self.state.compositor, &self.state.subcompositor)
+            else {
+                return Err("No WlSubcompositor loaded".into());
+            };
+            // the mirrored frame is shown once the frame of this output is presented
+            let surface = compositor.create_surface(&self.queue_handle, ());
+            let input_region = compositor.create_region(&self.queue_handle, ());
+            surface.set_input_region(Some(&input_region));
+            input_region.destroy();
+            let subsurface =
+                subcompositor.get_subsurface(&surface, &surfaces[&i], &self.queue_handle, ());
+            subsurface.set_desync();
+            let viewport = viewporter.get_viewport(&surface, &self.queue_handle, ());
+            self.state
+                .mirror_surfaces
+                .insert(i, (surface, subsurface, viewport));
         }
-        info!("> Screen frozen");
+        Ok(())
+    }
+    // makes the surface of an output a fullscreen toplevel on that output, for compositors without layer-shell
+    fn create_toplevel(&mut self, i: i64) -> Result<(), Box<dyn Error>> {
+        let (Some(surfaces), Some((wm_base, _)), Some(outputs)) = (
+            &self.state.surfaces,
+            &self.state.wm_base,
+            &self.state.outputs,
+        ) else {
+            return Err("Could not create a toplevel".into());
+        };
 
-        if &self.state.after_cmd != "" {
-            sleep(Duration::from_millis(self.state.after_timeout));
-            info!("> Running after-freeze commands: {}", &self.state.after_cmd);
-            Command::new("sh")
-                .arg("-c")
-                .arg(&self.state.after_cmd)
-                .spawn()
-                .expect("Failed to run after-freeze commands");
-        }
+        trace!("  creating toplevel {}", i);
+        let xdg_surface = wm_base.get_xdg_surface(&surfaces[&i], &self.queue_handle, i);
+        let toplevel = xdg_surface.get_toplevel(&self.queue_handle, i);
+        toplevel.set_title("wayfreeze".to_string());
+        toplevel.set_app_id(self.state.namespace.clone());
+        toplevel.set_fullscreen(Some(&outputs[&i]));
 
-        loop {
-            self.event_queue.blocking_dispatch(&mut self.state).unwrap();
-            if self.state.exit {
-                std::process::exit(0);
+        vec_insert(&mut self.state.xdg_surfaces, i, xdg_surface);
+        vec_insert(&mut self.state.toplevels, i, toplevel);
+        Ok(())
+    }
+    // creates & configures the layer surface that shows the frame of an output
+    fn create_layer_surface(&mut self, i: i64) -> Result<(), Box<dyn Error>> {
+        let Some(surfaces) = &self.state.surfaces else {
+            return Err("No WlSurface loaded".into());
+        };
+        let Some((layer_shell, _)) = &self.state.layer_shell else {
+            return Err("No ZwlrLayerShellV1 loaded".into());
+        };
+        let Some(outputs) = &self.state.outputs else {
+            return Err("Could not load WlOutputs".into());
+        };
+        let output = &outputs[&i];
+
+        trace!("  creating layer surface {}", i);
+        // create a layer surface for the current output & its surface
+        let ls = zwlr_layer_shell_v1::ZwlrLayerShellV1::get_layer_surface(
+            layer_shell,
+            &surfaces[&i],
+            Some(output),
+            self.state.layer.into(),
+            self.state.namespace.clone(),
+            &self.queue_handle,
+            i,
+        );
+
+        // configure layer surface
+        match self.state.geometry {
+            Some(_) => {
+                let Some(regions) = &self.state.regions else {
+                    return Err("Could not load regions".into());
+                };
+                let region = regions[&i];
+                // place the surface on top of the region, relative to the top left corner of the output
+                ls.set_anchor(Anchor::Top | Anchor::Left);
+                ls.set_margin(region.y, 0, 0, region.x);
+                ls.set_size(region.width as u32, region.height as u32);
+            }
+            None => {
+                ls.set_anchor(Anchor::Top | Anchor::Right | Anchor::Bottom | Anchor::Left);
             }
         }
+        ls.set_exclusive_zone(-1); // extend surface to anchored edges, ignore other exclusive zones
+        ls.set_keyboard_interactivity(self.state.keyboard_mode.into());
+
+        vec_insert(&mut self.state.layer_surfaces, i, ls);
+        Ok(())
     }
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(group = clap::ArgGroup::new("output_report").args(["list_outputs", "pick_output"]))]
 struct Args {
     /// Hide cursor when freezing the screen.
     #[arg(long, required = false, default_value_t = false)]
@@ -1018,23 +4490,1267 @@ struct Args {
     /// Amount of milliseconds to wait between freezing the screen and running after-freeze-cmd.
     #[arg(long, hide_default_value = true, required = false, default_value_t = 0)]
     after_freeze_timeout: u64,
+    /// Protocol used to copy the contents of the outputs.
+    #[arg(long, value_enum, required = false, default_value_t = CaptureBackend::Auto)]
+    capture_backend: CaptureBackend,
+    /// Layer to put the frozen frames on.
+    #[arg(long, value_enum, required = false, default_value_t = FreezeLayer::Overlay)]
+    layer: FreezeLayer,
+    /// Namespace of the layer surfaces, e.g. for layer rules of the compositor.
+    #[arg(long, required = false, default_value = "wayfreeze")]
+    namespace: String,
+    /// Whether the frozen surfaces take the keyboard, none leaves it to e.g. slurp or a launcher on top of the freeze.
+    #[arg(long, value_enum, required = false, default_value_t = KeyboardMode::Exclusive)]
+    keyboard: KeyboardMode,
+    /// Let pointer & keyboard input through to the windows underneath the frozen frame, exit with a signal or when the after-freeze command exits.
+    #[arg(
+        long,
+        required = false,
+        conflicts_with_all = ["keyboard", "pick_output", "burst"],
+        default_value_t = false
+    )]
+    passthrough: bool,
+    /// Only freeze this region of the screen, in the format slurp prints ("x,y wxh").
+    #[arg(long, required = false, allow_hyphen_values = true, value_parser = parse_geometry)]
+    geometry: Option<Region>,
+    /// Print the name, description & geometry of every output and exit.
+    #[arg(long, required = false, default_value_t = false)]
+    list_outputs: bool,
+    /// Show the frame of the output of which the name or description matches this glob pattern on all other frozen outputs.
+    #[arg(
+        long,
+        value_name = "SOURCE",
+        required = false,
+        conflicts_with_all = ["geometry", "burst", "replay", "freeze_on_change"]
+    )]
+    mirror: Option<String>,
+    /// How the mirrored frame is scaled onto outputs of a different size.
+    #[arg(long, value_enum, required = false, default_value_t = MirrorScaling::Fit)]
+    mirror_scaling: MirrorScaling,
+    /// Freeze every output, print the name & geometry of the one that gets clicked (or of which the number is pressed) and exit.
+    #[arg(
+        long,
+        required = false,
+        conflicts_with_all = ["list_outputs", "geometry", "burst", "replay", "freeze_on_change", "mirror", "passthrough"],
+        default_value_t = false
+    )]
+    pick_output: bool,
+    /// Print the output list or the picked output as JSON.
+    #[arg(
+        long,
+        required = false,
+        requires = "output_report",
+        default_value_t = false
+    )]
+    json: bool,
+    /// Only freeze outputs of which the name or description matches this glob pattern, can be used multiple times.
+    #[arg(long = "output", value_name = "NAME", required = false)]
+    outputs: Vec<String>,
+    /// Only freeze the output that has focus, asks Sway, i3 or Hyprland over their IPC socket.
+    #[arg(
+        long,
+        required = false,
+        conflicts_with = "outputs",
+        default_value_t = false
+    )]
+    focused_output: bool,
+    /// Don't freeze outputs of which the name or description matches this glob pattern, can be used multiple times.
+    #[arg(long = "exclude-output", value_name = "NAME", required = false)]
+    exclude_outputs: Vec<String>,
+    /// Amount of times to retry capturing an output after it failed.
+    #[arg(long, required = false, default_value_t = 0)]
+    capture_retries: u32,
+    /// Amount of milliseconds to wait before the first retry, doubles with every retry.
+    #[arg(long, required = false, default_value_t = 50)]
+    capture_retry_delay: u64,
+    /// What to do when an output can't be captured, even after retrying.
+    #[arg(long, value_enum, required = false, default_value_t = CaptureFailurePolicy::Abort)]
+    on_capture_failure: CaptureFailurePolicy,
+    /// Uncover the outputs while refreezing, for compositors that include wayfreeze's own surfaces in captures.
+    #[arg(long, required = false, default_value_t = false)]
+    refreeze_uncover: bool,
+    /// Wait until the screen changes & freeze the changed frame.
+    #[arg(long, required = false, default_value_t = false)]
+    freeze_on_change: bool,
+    /// Only react to changes within this region of the screen, in the format slurp prints ("x,y wxh").
+    #[arg(long, required = false, requires = "freeze_on_change", allow_hyphen_values = true, value_parser = parse_geometry)]
+    change_geometry: Option<Region>,
+    /// Amount of frames to capture per output, step through them with the arrow keys & press enter to keep one.
+    #[arg(long, required = false, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    burst: u32,
+    /// Amount of milliseconds to wait between the frames of a burst.
+    #[arg(long, required = false, default_value_t = 100)]
+    burst_interval: u64,
+    /// Keep capturing in the background, freeze to what was on screen this many milliseconds ago on SIGUSR2.
+    #[arg(long, required = false, conflicts_with_all = ["freeze_on_change", "burst"])]
+    replay: Option<u64>,
+    /// Amount of milliseconds between the frames that are kept for --replay.
+    #[arg(long, required = false, default_value_t = 50)]
+    replay_interval: u64,
+    /// Unix socket to create while recording for --replay, writing "freeze" to it freezes the screen.
+    #[arg(long, required = false, requires = "replay")]
+    replay_socket: Option<PathBuf>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
     let args = Args::parse();
     info!("> Parsed arguments");
+    let (list_outputs, json) = (args.list_outputs, args.json);
 
-    match ScreenFreezer::new(
-        args.hide_cursor,
-        args.before_freeze_cmd,
-        args.after_freeze_cmd,
-        args.before_freeze_timeout,
-        args.after_freeze_timeout,
-    ) {
-        Ok(mut sf) => sf.freeze().unwrap(),
-        Err(e) => panic!("Could not create ScreenFreezer: {}", e),
+    match ScreenFreezer::new(args) {
+        Ok(mut sf) if list_outputs => sf.list_outputs(json)?,
+        Ok(mut sf) => sf.freeze()?,
+        Err(e) => {
+            error!("Could not create ScreenFreezer: {}", e);
+            std::process::exit(1);
+        }
     };
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wl_output::Transform;
+
+    const TRANSFORMS: [Transform; 8] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    // applies a wl_output transform to an image: optionally flip around the vertical axis,
+    // then rotate counter-clockwise in steps of 90 degrees
+    fn apply(transform: Transform, image: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let (flipped, rotations) = match transform {
+            Transform::Normal => (false, 0),
+            Transform::_90 => (false, 1),
+            Transform::_180 => (false, 2),
+            Transform::_270 => (false, 3),
+            Transform::Flipped => (true, 0),
+            Transform::Flipped90 => (true, 1),
+            Transform::Flipped180 => (true, 2),
+            Transform::Flipped270 => (true, 3),
+            _ => unreachable!(),
+        };
+        let mut image = image.to_vec();
+        if flipped {
+            image.iter_mut().for_each(|row| row.reverse());
+        }
+        for _ in 0..rotations {
+            let (height, width) = (image.len(), image[0].len());
+            image = (0..width)
+                .map(|y| (0..height).map(|x| image[x][width - 1 - y]).collect())
+                .collect();
+        }
+        image
+    }
+
+    // a connection nobody answers on, requests are only buffered until they're flushed
+    fn test_connection() -> (Connection, EventQueue<AppData>, UnixStream) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let connection = Connection::from_socket(client).unwrap();
+        let queue = connection.new_event_queue();
+        (connection, queue, server)
+    }
+
+    // binds wl_shm without asking the compositor, so that buffers can be created
+    fn bind_shm(state: &mut AppData, connection: &Connection, queue_handle: &QueueHandle<AppData>) {
+        let registry = connection.display().get_registry(queue_handle, ());
+        state.shm = Some((registry.bind(1, 1, queue_handle, ()), 1));
+    }
+
+    #[test]
+    fn buffer_transform_with_y_invert() {
+        let table = [
+            (Transform::Normal, false, Transform::Normal),
+            (Transform::_90, false, Transform::_90),
+            (Transform::_180, false, Transform::_180),
+            (Transform::_270, false, Transform::_270),
+            (Transform::Flipped, false, Transform::Flipped),
+            (Transform::Flipped90, false, Transform::Flipped90),
+            (Transform::Flipped180, false, Transform::Flipped180),
+            (Transform::Flipped270, false, Transform::Flipped270),
+            (Transform::Normal, true, Transform::Flipped180),
+            (Transform::_90, true, Transform::Flipped90),
+            (Transform::_180, true, Transform::Flipped),
+            (Transform::_270, true, Transform::Flipped270),
+            (Transform::Flipped, true, Transform::_180),
+            (Transform::Flipped90, true, Transform::_90),
+            (Transform::Flipped180, true, Transform::Normal),
+            (Transform::Flipped270, true, Transform::_270),
+        ];
+        for (output_transform, y_invert, expected) in table {
+            assert_eq!(
+                buffer_transform(output_transform, y_invert),
+                expected,
+                "output transform {:?}, y_invert {}",
+                output_transform,
+                y_invert
+            );
+        }
+    }
+
+    #[test]
+    fn frames_of_transformed_outputs() {
+        // a 2560x1440 panel at scale 2, the frames are captured in its native orientation
+        let info = ShmBufferInfo {
+            format: wl_shm::Format::Xrgb8888,
+            width: 5120,
+            height: 2880,
+            stride: 5120 * 4,
+        };
+        for transform in TRANSFORMS {
+            let logical = transformed_size((2560, 1440), transform);
+            let mut state = AppData::default();
+            vec_insert(&mut state.transforms, 0, transform);
+            vec_insert(&mut state.output_scales, 0, 2);
+            state.update_mode(0, mode(5120, 2880, 60000, wl_output::Mode::Current));
+            assert_eq!(
+                state.derived_logical_size(0),
+                Some(logical),
+                "{:?}",
+                transform
+            );
+            state.set_logical_size(0, logical.0, logical.1);
+            assert_eq!(state.output_info(0).scale, 2.0, "{:?}", transform);
+            let region = state.output_region(0).unwrap();
+            vec_insert(&mut state.regions, 0, region);
+            vec_insert(&mut state.shm_buffer_infos, 0, info);
+            assert_eq!(state.surface_transform(0), transform);
+            assert!(state.frame_size_matches(0), "{:?}", transform);
+            // without a viewport, the transformed buffer is exactly twice the size of the surface
+            assert_eq!(state.buffer_scale(0), 2, "{:?}", transform);
+
+            // a frame that was rotated already would be stretched
+            let rotated = ShmBufferInfo {
+                width: 2880,
+                height: 5120,
+                stride: 2880 * 4,
+                ..info
+            };
+            vec_insert(&mut state.shm_buffer_infos, 0, rotated);
+            assert!(!state.frame_size_matches(0), "{:?}", transform);
+            assert_eq!(state.buffer_scale(0), 1, "{:?}", transform);
+
+            // ext-image-copy-capture frames carry their own transform
+            vec_insert(&mut state.frame_transforms, 0, Transform::Normal);
+            assert_eq!(state.surface_transform(0), Transform::Normal);
+            assert_eq!(state.frame_size_matches(0), swaps_axes(transform));
+        }
+    }
+
+    #[test]
+    fn viewport_crops_transformed_frames() {
+        let info = ShmBufferInfo {
+            format: wl_shm::Format::Xrgb8888,
+            width: 3840,
+            height: 2160,
+            stride: 3840 * 4,
+        };
+        for transform in TRANSFORMS {
+            let (width, height) = transformed_size((1920, 1080), transform);
+            let mut state = AppData {
+                geometry: Some(Region {
+                    x: 100,
+                    y: 200,
+                    width: 300,
+                    height: 400,
+                }),
+                capture_backend: CaptureBackend::ExtImageCopyCapture,
+                ..Default::default()
+            };
+            vec_insert(&mut state.positions, 0, (0, 0));
+            vec_insert(&mut state.widths, 0, width);
+            vec_insert(&mut state.heights, 0, height);
+            vec_insert(&mut state.frame_transforms, 0, transform);
+            let region = state.output_region(0).unwrap();
+            vec_insert(&mut state.regions, 0, region);
+            vec_insert(&mut state.shm_buffer_infos, 0, info);
+            // the source rectangle is in surface coordinates, after the buffer transform
+            assert_eq!(
+                state.viewport_source(0),
+                (200.0, 400.0, 600.0, 800.0),
+                "{:?}",
+                transform
+            );
+            assert!(state.frame_size_matches(0), "{:?}", transform);
+        }
+    }
+
+    #[test]
+    fn logical_regions_map_onto_transformed_buffers() {
+        // 3x2 output, a single changed pixel in its top right corner
+        let mut surface = vec![vec![0; 3]; 2];
+        surface[0][2] = 1;
+        let changed = Region {
+            x: 2,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        for transform in TRANSFORMS {
+            let buffer = apply(transform, &surface);
+            let (width, height) = (buffer[0].len() as i32, buffer.len() as i32);
+            let rect = logical_to_buffer(changed, transform, (3, 2), (width, height));
+            assert_eq!(
+                (rect.width, rect.height),
+                (1, 1),
+                "transform {:?}",
+                transform
+            );
+            assert_eq!(
+                buffer[rect.y as usize][rect.x as usize], 1,
+                "transform {:?}",
+                transform
+            );
+        }
+        // logical coordinates get scaled up to the buffer size
+        assert_eq!(
+            logical_to_buffer(
+                Region {
+                    x: 10,
+                    y: 5,
+                    width: 20,
+                    height: 10
+                },
+                Transform::Normal,
+                (100, 50),
+                (200, 100)
+            ),
+            Region {
+                x: 20,
+                y: 10,
+                width: 40,
+                height: 20
+            }
+        );
+    }
+
+    fn mode(width: i32, height: i32, refresh: i32, flags: wl_output::Mode) -> OutputMode {
+        OutputMode {
+            width,
+            height,
+            refresh,
+            flags,
+        }
+    }
+
+    fn physical_size(state: &AppData, output: i64) -> (i32, i32) {
+        (
+            state.phys_widths.as_ref().unwrap()[&output],
+            state.phys_heights.as_ref().unwrap()[&output],
+        )
+    }
+
+    #[test]
+    fn current_mode_wins_over_last_mode() {
+        use wl_output::Mode;
+        let mut state = AppData::default();
+        // the current mode is advertised first, followed by every other mode
+        state.update_mode(0, mode(2560, 1440, 144000, Mode::Current | Mode::Preferred));
+        state.update_mode(0, mode(1920, 1080, 60000, Mode::empty()));
+        state.update_mode(0, mode(1280, 720, 60000, Mode::empty()));
+        assert_eq!(physical_size(&state, 0), (2560, 1440));
+        // outputs are tracked separately
+        state.update_mode(1, mode(1920, 1080, 60000, Mode::empty()));
+        state.update_mode(1, mode(3840, 2160, 60000, Mode::Current));
+        state.update_mode(1, mode(1024, 768, 60000, Mode::empty()));
+        assert_eq!(physical_size(&state, 1), (3840, 2160));
+        assert_eq!(physical_size(&state, 0), (2560, 1440));
+        assert_eq!(state.modes.as_ref().unwrap()[&1].len(), 3);
+    }
+
+    #[test]
+    fn preferred_mode_without_current_mode() {
+        use wl_output::Mode;
+        let mut state = AppData::default();
+        state.update_mode(0, mode(1920, 1080, 60000, Mode::empty()));
+        state.update_mode(0, mode(2560, 1600, 60000, Mode::Preferred));
+        state.update_mode(0, mode(1280, 800, 60000, Mode::empty()));
+        assert_eq!(physical_size(&state, 0), (2560, 1600));
+        // without any flags, the last mode is the best guess
+        state.update_mode(1, mode(1280, 800, 60000, Mode::empty()));
+        state.update_mode(1, mode(1920, 1200, 60000, Mode::empty()));
+        assert_eq!(physical_size(&state, 1), (1920, 1200));
+    }
+
+    #[test]
+    fn current_mode_changes_while_running() {
+        use wl_output::Mode;
+        let mut state = AppData::default();
+        state.update_mode(0, mode(2560, 1440, 60000, Mode::Current | Mode::Preferred));
+        state.update_mode(0, mode(1920, 1080, 60000, Mode::empty()));
+        // the compositor switches modes & only resends the new current mode
+        state.update_mode(0, mode(1920, 1080, 60000, Mode::Current));
+        assert_eq!(physical_size(&state, 0), (1920, 1080));
+        let modes = &state.modes.as_ref().unwrap()[&0];
+        assert_eq!(modes.len(), 2);
+        assert_eq!(modes[0].flags, Mode::Preferred);
+        // and back again
+        state.update_mode(0, mode(2560, 1440, 60000, Mode::Current | Mode::Preferred));
+        assert_eq!(physical_size(&state, 0), (2560, 1440));
+        assert_eq!(
+            current_mode(&state.modes.as_ref().unwrap()[&0]),
+            Some(mode(2560, 1440, 60000, Mode::Current | Mode::Preferred))
+        );
+    }
+
+    #[test]
+    fn unplugged_output_leaves_nothing_behind() {
+        let mut state = AppData::default();
+        for output in [0, 1] {
+            vec_insert(&mut state.widths, output, 1920);
+            vec_insert(&mut state.heights, output, 1080);
+            vec_insert(&mut state.positions, output, (1920 * output as i32, 0));
+            vec_insert(&mut state.names, output, format!("DP-{}", output));
+            vec_insert(
+                &mut state.regions,
+                output,
+                Region {
+                    x: 0,
+                    y: 0,
+                    width: 1920,
+                    height: 1080,
+                },
+            );
+            state.update_mode(output, mode(1920, 1080, 60000, wl_output::Mode::Current));
+            state.output_globals.insert(output, 40 + output as u32);
+            state.captured_outputs.push(output);
+        }
+        state.output_count = 2;
+        state.outputs_ready = 2;
+        state.frames_ready = 2;
+
+        state.remove_output(0);
+        assert_eq!(state.frozen_outputs(), vec![1]);
+        assert_eq!(state.output_region(0), None);
+        assert!(state.output_region(1).is_some());
+        assert!(!state.names.as_ref().unwrap().contains_key(&0));
+        assert!(!state.modes.as_ref().unwrap().contains_key(&0));
+        assert!(!state.phys_widths.as_ref().unwrap().contains_key(&0));
+        assert!(!state.output_globals.contains_key(&0));
+        assert_eq!(state.captured_outputs, vec![1]);
+        assert_eq!(
+            (state.output_count, state.outputs_ready, state.frames_ready),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn output_layout_model() {
+        let mut state = AppData::default();
+        vec_insert(&mut state.names, 0, "DP-1".to_string());
+        vec_insert(&mut state.makes, 0, "Dell Inc.".to_string());
+        vec_insert(&mut state.models, 0, "DELL U2720Q".to_string());
+        vec_insert(&mut state.positions, 0, (1440, 0));
+        vec_insert(&mut state.widths, 0, 2560);
+        vec_insert(&mut state.heights, 0, 1440);
+        vec_insert(&mut state.transforms, 0, Transform::Normal);
+        vec_insert(&mut state.output_scales, 0, 2);
+        state.update_mode(0, mode(3840, 2160, 59997, wl_output::Mode::Current));
+        // rotated output without a mode
+        vec_insert(&mut state.positions, 1, (0, 0));
+        vec_insert(&mut state.widths, 1, 1440);
+        vec_insert(&mut state.heights, 1, 2560);
+        vec_insert(&mut state.transforms, 1, Transform::_90);
+
+        let info = state.output_info(0);
+        assert_eq!(info.name.as_deref(), Some("DP-1"));
+        assert_eq!(
+            (info.x, info.y, info.width, info.height),
+            (1440, 0, 2560, 1440)
+        );
+        assert_eq!(info.scale, 1.5);
+        assert_eq!(
+            info.mode,
+            Some(ModeInfo {
+                width: 3840,
+                height: 2160,
+                refresh: 59.997
+            })
+        );
+        assert_eq!(
+            info.to_string(),
+            "DP-1\n  Make & model: Dell Inc. DELL U2720Q\n  Geometry: 1440,0 2560x1440\n  Mode: 3840x2160 @ 59.997 Hz\n  Scale: 1.5\n  Transform: normal"
+        );
+        let info = state.output_info(1);
+        assert_eq!((info.scale, info.transform, &info.mode), (1.0, "90", &None));
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["transform"], "90");
+        assert_eq!(json["height"], 2560);
+        assert!(json["name"].is_null());
+    }
+
+    #[test]
+    fn logical_size_without_xdg_output() {
+        let mut state = AppData {
+            output_count: 2,
+            ..Default::default()
+        };
+        vec_insert(&mut state.output_scales, 0, 2);
+        vec_insert(&mut state.transforms, 0, Transform::Normal);
+        state.update_mode(0, mode(3840, 2160, 60000, wl_output::Mode::Current));
+        vec_insert(&mut state.transforms, 1, Transform::Flipped270);
+        state.update_mode(1, mode(1920, 1080, 60000, wl_output::Mode::Current));
+        // no scale event means a scale of 1
+        assert_eq!(state.derived_logical_size(0), Some((1920, 1080)));
+        assert_eq!(state.derived_logical_size(1), Some((1080, 1920)));
+        assert_eq!(state.derived_logical_size(2), None);
+
+        state.derive_logical_size(0);
+        state.derive_logical_size(1);
+        // sent again when anything changes, only counted once
+        state.derive_logical_size(1);
+        assert_eq!(state.outputs_ready, state.output_count);
+        assert_eq!(
+            state.output_region(1).map(|r| (r.width, r.height)),
+            Some((1080, 1920))
+        );
+    }
+
+    #[test]
+    fn integer_scale_without_viewporter() {
+        let mut state = AppData::default();
+        vec_insert(
+            &mut state.regions,
+            0,
+            Region {
+                x: 0,
+                y: 0,
+                width: 1280,
+                height: 800,
+            },
+        );
+        vec_insert(&mut state.transforms, 0, Transform::_90);
+        vec_insert(&mut state.output_scales, 0, 2);
+        let info = |width, height| ShmBufferInfo {
+            format: wl_shm::Format::Xrgb8888,
+            width,
+            height,
+            stride: width * 4,
+        };
+        // rotated buffer, twice the logical size
+        vec_insert(&mut state.shm_buffer_infos, 0, info(1600, 2560));
+        assert_eq!(state.buffer_scale(0), 2);
+        // the scale preferred by the surface wins
+        state.buffer_scales.insert(0, 3);
+        assert_eq!(state.buffer_scale(0), 1);
+        state.buffer_scales.insert(0, 2);
+        // blank buffers are created at the logical size
+        assert_eq!(state.pixel_buffer_size(0), (1280, 800));
+        vec_insert(&mut state.shm_buffer_infos, 0, info(1280, 800));
+        assert_eq!(state.buffer_scale(0), 1);
+    }
+
+    #[test]
+    fn changed_outputs_are_captured_again() {
+        let mut state = AppData::default();
+        vec_insert(&mut state.positions, 0, (0, 0));
+        vec_insert(&mut state.transforms, 0, Transform::Normal);
+        state.update_mode(0, mode(1920, 1080, 60000, wl_output::Mode::Current));
+        state.set_logical_size(0, 1920, 1080);
+        state.configured_surfaces.insert(0, 1);
+        // changes before freezing are picked up when capturing
+        state.update_mode(0, mode(2560, 1440, 60000, wl_output::Mode::Current));
+        assert!(state.changed_outputs.is_empty());
+
+        state.frozen = true;
+        // the same mode & size again
+        state.update_mode(0, mode(2560, 1440, 60000, wl_output::Mode::Current));
+        state.set_logical_size(0, 1920, 1080);
+        assert!(state.changed_outputs.is_empty());
+        // a new mode, followed by the logical size that goes with it
+        state.update_mode(0, mode(3840, 2160, 60000, wl_output::Mode::Current));
+        state.set_logical_size(0, 2560, 1440);
+        assert_eq!(state.changed_outputs, vec![0]);
+        assert_eq!(
+            state.output_region(0).map(|r| (r.width, r.height)),
+            Some((2560, 1440))
+        );
+
+        // outputs without a surface aren't frozen
+        state.update_mode(1, mode(1920, 1080, 60000, wl_output::Mode::Current));
+        state.update_mode(1, mode(1280, 720, 60000, wl_output::Mode::Current));
+        assert_eq!(state.changed_outputs, vec![0]);
+        state.remove_output(0);
+        assert!(state.changed_outputs.is_empty());
+    }
+
+    #[test]
+    fn output_hints() {
+        let pixel = |pixels: &[u8], width: usize, x: usize, y: usize| {
+            pixels[(y * width + x) * 4..(y * width + x) * 4 + 4].to_vec()
+        };
+        // 250 pixels high -> cells of 10 pixels, "1" is 30x50 pixels in the middle
+        let pixels = render_hint(400, 250, 1);
+        assert_eq!(pixels.len(), 400 * 250 * 4);
+        assert_eq!(pixel(&pixels, 400, 0, 0), [0, 0, 0, 0x99]);
+        assert_eq!(pixel(&pixels, 400, 399, 249), [0, 0, 0, 0x99]);
+        // the stem of the 1 & the empty top left corner next to it
+        assert_eq!(pixel(&pixels, 400, 200, 105), [0xff; 4]);
+        assert_eq!(pixel(&pixels, 400, 190, 100), [0, 0, 0, 0x99]);
+        // the bottom row is filled
+        assert_eq!(pixel(&pixels, 400, 186, 145), [0xff; 4]);
+        assert_eq!(pixel(&pixels, 400, 214, 145), [0xff; 4]);
+
+        // numbers that don't fit are squeezed together, down to a pixel per cell
+        let pixels = render_hint(3, 5, 10);
+        assert_eq!(pixels.len(), 3 * 5 * 4);
+    }
+
+    #[test]
+    fn mirrored_frames_are_scaled_onto_other_outputs() {
+        let region = |x, y, width, height| Region {
+            x,
+            y,
+            width,
+            height,
+        };
+        // a 16:10 laptop panel onto a 16:9 projector
+        let (frame, output) = ((2880, 1800), (1920, 1080));
+        assert_eq!(
+            mirror_placement(frame, output, MirrorScaling::Fit),
+            ((0.0, 0.0, 2880.0, 1800.0), region(96, 0, 1728, 1080))
+        );
+        assert_eq!(
+            mirror_placement(frame, output, MirrorScaling::Fill),
+            ((0.0, 90.0, 2880.0, 1620.0), region(0, 0, 1920, 1080))
+        );
+        assert_eq!(
+            mirror_placement(frame, output, MirrorScaling::Stretch),
+            ((0.0, 0.0, 2880.0, 1800.0), region(0, 0, 1920, 1080))
+        );
+        // onto a portrait output
+        let (source, placement) = mirror_placement((1920, 1080), (1080, 1920), MirrorScaling::Fit);
+        assert_eq!(source, (0.0, 0.0, 1920.0, 1080.0));
+        assert_eq!(placement, region(0, 656, 1080, 608));
+        let (source, _) = mirror_placement((1920, 1080), (1080, 1920), MirrorScaling::Fill);
+        assert_eq!(source, (656.25, 0.0, 607.5, 1080.0));
+    }
+
+    #[test]
+    fn globals_are_bound_at_supported_versions() {
+        // newer than wayfreeze supports
+        assert_eq!(negotiate_version("zwlr_screencopy_manager_v1", 7), 3);
+        assert_eq!(negotiate_version("wl_compositor", 6), 6);
+        // older compositors
+        assert_eq!(negotiate_version("zwlr_screencopy_manager_v1", 1), 1);
+        assert_eq!(negotiate_version("wl_output", 2), 2);
+        // the generated bindings can't go beyond their own version either
+        for (interface, version) in MAX_VERSIONS {
+            let generated = [
+                wl_compositor::WlCompositor::interface(),
+                wl_output::WlOutput::interface(),
+                wl_seat::WlSeat::interface(),
+                wl_shm::WlShm::interface(),
+                wl_subcompositor::WlSubcompositor::interface(),
+                WpFractionalScaleManagerV1::interface(),
+                WpViewporter::interface(),
+                ZxdgOutputManagerV1::interface(),
+                ZwlrScreencopyManagerV1::interface(),
+                zwlr_layer_shell_v1::ZwlrLayerShellV1::interface(),
+                XdgWmBase::interface(),
+                ExtOutputImageCaptureSourceManagerV1::interface(),
+                ExtImageCopyCaptureManagerV1::interface(),
+            ]
+            .into_iter()
+            .find(|i| i.name == interface)
+            .unwrap();
+            assert!(version <= generated.version, "{}", interface);
+        }
+    }
+
+    fn region(x: i32, y: i32, width: i32, height: i32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn geometry_parsing() {
+        let table = [
+            ("10,20 300x400", Some(region(10, 20, 300, 400))),
+            (
+                "-1280,-200 1920x1080",
+                Some(region(-1280, -200, 1920, 1080)),
+            ),
+            (" 0,0 1x1\n", Some(region(0, 0, 1, 1))),
+            ("10,10 5x", None),
+            ("10,10 x5", None),
+            ("10,10", None),
+            ("10 10x10", None),
+            ("10,10 10x10x10", None),
+            ("a,b cxd", None),
+            ("1.5,0 10x10", None),
+            ("0,0 0x10", None),
+            ("0,0 10x0", None),
+            ("0,0 -10x10", None),
+            ("", None),
+        ];
+        for (geometry, expected) in table {
+            assert_eq!(parse_geometry(geometry).ok(), expected, "{:?}", geometry);
+        }
+    }
+
+    #[test]
+    fn region_intersections() {
+        let output = region(0, 0, 1920, 1080);
+        let table = [
+            (region(100, 100, 200, 200), Some(region(100, 100, 200, 200))),
+            (region(-100, -50, 200, 100), Some(region(0, 0, 100, 50))),
+            (
+                region(1800, 1000, 500, 500),
+                Some(region(1800, 1000, 120, 80)),
+            ),
+            (region(-500, -500, 5000, 5000), Some(output)),
+            // only touching an edge or not overlapping at all
+            (region(1920, 0, 100, 100), None),
+            (region(0, -100, 100, 100), None),
+            (region(3000, 3000, 10, 10), None),
+        ];
+        for (geometry, expected) in table {
+            assert_eq!(geometry.intersect(&output), expected, "{:?}", geometry);
+            assert_eq!(output.intersect(&geometry), expected, "{:?}", geometry);
+        }
+    }
+
+    #[test]
+    fn geometry_per_output() {
+        // an output to the left of the origin, one at it & one to the right of it
+        let mut state = AppData::default();
+        for (output, (x, y, width, height)) in [
+            (-1280, 0, 1280, 1024),
+            (0, 0, 1920, 1080),
+            (1920, 0, 2560, 1440),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            vec_insert(&mut state.positions, output as i64, (x, y));
+            vec_insert(&mut state.widths, output as i64, width);
+            vec_insert(&mut state.heights, output as i64, height);
+        }
+        let table = [
+            // spans the two outputs on the right, in output-local coordinates
+            (
+                region(1800, 100, 300, 200),
+                [
+                    None,
+                    Some(region(1800, 100, 120, 200)),
+                    Some(region(0, 100, 180, 200)),
+                ],
+            ),
+            // negative offsets
+            (
+                region(-100, 50, 200, 100),
+                [
+                    Some(region(1180, 50, 100, 100)),
+                    Some(region(0, 50, 100, 100)),
+                    None,
+                ],
+            ),
+            // ends right where the next output starts
+            (
+                region(1820, 0, 100, 10),
+                [None, Some(region(1820, 0, 100, 10)), None],
+            ),
+            (region(5000, 5000, 10, 10), [None, None, None]),
+        ];
+        for (geometry, expected) in table {
+            state.geometry = Some(geometry);
+            for (output, expected) in expected.into_iter().enumerate() {
+                assert_eq!(
+                    state.output_region(output as i64),
+                    expected,
+                    "{:?} on output {}",
+                    geometry,
+                    output
+                );
+            }
+        }
+        // without a geometry, every output is frozen completely
+        state.geometry = None;
+        assert_eq!(state.output_region(0), Some(region(0, 0, 1280, 1024)));
+    }
+
+    #[test]
+    fn output_name_globs() {
+        assert!(glob_match("eDP-1", "eDP-1"));
+        assert!(glob_match("eDP-*", "eDP-1"));
+        assert!(glob_match("HDMI-A-?", "HDMI-A-2"));
+        assert!(glob_match("*Dell*", "Dell Inc. DELL U2720Q 1234"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*-*-1", "DP-3-1"));
+        assert!(!glob_match("DP-1", "DP-10"));
+        assert!(!glob_match("DP-?", "DP-10"));
+        assert!(!glob_match("*Dell", "Dell Inc."));
+    }
+
+    #[test]
+    fn replay_socket_is_only_replaced_when_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wayfreeze.sock");
+        let listener = bind_socket(&path).unwrap();
+        // a running instance keeps its socket
+        assert!(bind_socket(&path).is_err());
+        assert!(path.exists());
+        // the socket of an instance that's gone is replaced
+        drop(listener);
+        assert!(bind_socket(&path).is_ok());
+    }
+
+    #[test]
+    fn replay_commands_are_read_across_partial_lines() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut pending = Vec::new();
+        // nothing was sent yet
+        assert_eq!(
+            read_socket_commands(&mut server, &mut pending),
+            (vec![], true)
+        );
+        client.write_all(b"fre").unwrap();
+        assert_eq!(
+            read_socket_commands(&mut server, &mut pending),
+            (vec![], true)
+        );
+        client.write_all(b"eze\nfoo").unwrap();
+        assert_eq!(
+            read_socket_commands(&mut server, &mut pending),
+            (vec!["freeze".to_string()], true)
+        );
+        // the last command doesn't need a newline
+        drop(client);
+        assert_eq!(
+            read_socket_commands(&mut server, &mut pending),
+            (vec!["foo".to_string()], false)
+        );
+
+        // a client that never ends its line is dropped
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut pending = Vec::new();
+        client.write_all(&[b'x'; MAX_REPLAY_COMMAND_LEN]).unwrap();
+        assert_eq!(
+            read_socket_commands(&mut server, &mut pending),
+            (vec![], true)
+        );
+        client.write_all(b"x").unwrap();
+        assert_eq!(
+            read_socket_commands(&mut server, &mut pending),
+            (vec![], false)
+        );
+    }
+
+    #[test]
+    fn focused_output_matches_names_exactly() {
+        let mut state = AppData {
+            focused_output: Some("DP-1".to_string()),
+            ..Default::default()
+        };
+        vec_insert(&mut state.names, 0, "DP-1".to_string());
+        vec_insert(&mut state.names, 1, "DP-10".to_string());
+        vec_insert(&mut state.names, 2, "HDMI-A-1".to_string());
+        vec_insert(&mut state.descriptions, 2, "DP-1".to_string());
+        assert!(state.output_selected(0));
+        assert!(!state.output_selected(1));
+        // descriptions can't be mistaken for the focused output
+        assert!(!state.output_selected(2));
+        // an output without a name can't be the focused one
+        assert!(!state.output_selected(3));
+    }
+
+    // a 1x1 frame, as if it was captured
+    fn test_frame(state: &AppData, queue_handle: &QueueHandle<AppData>) -> CapturedFrame {
+        let (buffer, pool) = state
+            .create_pixel_buffer(wl_shm::Format::Xrgb8888, [0; 4], (1, 1), queue_handle)
+            .unwrap();
+        CapturedFrame {
+            buffer,
+            pool,
+            info: ShmBufferInfo {
+                format: wl_shm::Format::Xrgb8888,
+                width: 1,
+                height: 1,
+                stride: 4,
+            },
+            flags: None,
+        }
+    }
+
+    // an output that failed to be captured with the given policy & retries
+    fn failing_output(policy: CaptureFailurePolicy, retries: u32) -> AppData {
+        let mut state = AppData {
+            on_capture_failure: policy,
+            capture_retries: retries,
+            capture_retry_delay: 50,
+            ..Default::default()
+        };
+        let region = Region {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        vec_insert(&mut state.regions, 0, region);
+        vec_insert(&mut state.regions, 1, region);
+        state
+    }
+
+    #[test]
+    fn failed_captures_are_retried_with_backoff() {
+        let (_connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = failing_output(CaptureFailurePolicy::Abort, 3);
+        for (attempt, delay) in [(1, 50), (2, 100), (3, 200)] {
+            let before = Instant::now();
+            state.capture_failed(0, &queue_handle);
+            let deadline = state.capture_retry_deadlines[&0];
+            assert_eq!(state.capture_attempts[&0], attempt);
+            assert!(deadline >= before + Duration::from_millis(delay));
+            assert!(deadline <= Instant::now() + Duration::from_millis(delay));
+            assert!(!state.exit);
+            // the deadline passed & the retry failed as well
+            state.capture_retry_deadlines.remove(&0);
+        }
+        // out of retries
+        state.capture_failed(0, &queue_handle);
+        assert_eq!(state.capture_attempts[&0], 3);
+        assert!(state.capture_retry_deadlines.is_empty());
+        assert!(state.exit);
+    }
+
+    #[test]
+    fn capture_failure_policies() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+
+        let mut state = failing_output(CaptureFailurePolicy::Abort, 0);
+        state.capture_failed(0, &queue_handle);
+        assert!(state.exit);
+
+        let mut state = failing_output(CaptureFailurePolicy::Skip, 0);
+        state.capture_failed(0, &queue_handle);
+        assert!(!state.exit);
+        assert!(!state.regions.as_ref().unwrap().contains_key(&0));
+        assert!(state.regions.as_ref().unwrap().contains_key(&1));
+        assert!(state.blank_outputs.is_empty());
+        assert_eq!(state.frames_ready, 0);
+
+        let mut state = failing_output(CaptureFailurePolicy::Blank, 0);
+        bind_shm(&mut state, &connection, &queue_handle);
+        state.capture_failed(0, &queue_handle);
+        assert!(!state.exit);
+        assert!(state.regions.as_ref().unwrap().contains_key(&0));
+        assert_eq!(state.blank_outputs, vec![0]);
+        assert_eq!(state.captured_outputs, vec![0]);
+        assert_eq!(state.frames_ready, 1);
+        assert!(state.buffers.as_ref().unwrap().contains_key(&0));
+
+        // covering it needs wl_shm, without it there's nothing left but to exit
+        let mut state = failing_output(CaptureFailurePolicy::Blank, 0);
+        state.capture_failed(0, &queue_handle);
+        assert!(state.exit);
+    }
+
+    #[test]
+    fn failed_refreeze_keeps_previous_frame() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = failing_output(CaptureFailurePolicy::Abort, 0);
+        bind_shm(&mut state, &connection, &queue_handle);
+        let frame = test_frame(&state, &queue_handle);
+        let buffer = frame.buffer.clone();
+        state.presented_frames.insert(0, frame);
+        state.frozen = true;
+        state.refreezing = true;
+        state.refreeze_outputs = vec![0];
+
+        state.capture_failed(0, &queue_handle);
+        // the policy only applies to the first freeze
+        assert!(!state.exit);
+        assert!(state.regions.as_ref().unwrap().contains_key(&0));
+        assert_eq!(state.captured_outputs, vec![0]);
+        assert_eq!(state.frames_ready, 1);
+
+        state.finish_refreeze(&queue_handle);
+        assert!(!state.refreezing);
+        assert_eq!(state.buffers.as_ref().unwrap()[&0], buffer);
+        assert!(state.blank_outputs.is_empty());
+    }
+
+    #[test]
+    fn burst_stepping_wraps_around() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = AppData::default();
+        bind_shm(&mut state, &connection, &queue_handle);
+        let frames: Vec<CapturedFrame> =
+            (0..3).map(|_| test_frame(&state, &queue_handle)).collect();
+        let buffers: Vec<wl_buffer::WlBuffer> = frames.iter().map(|f| f.buffer.clone()).collect();
+        state.burst_frames.insert(0, frames);
+        let shown = |state: &AppData| {
+            let shown = &state.buffers.as_ref().unwrap()[&0];
+            buffers.iter().position(|b| b == shown).unwrap()
+        };
+
+        state.load_burst_frame(0);
+        assert_eq!(shown(&state), 0);
+        // stepping back from the first frame shows the last one & the other way around
+        for (step, expected) in [(-1, 2), (1, 0), (1, 1), (1, 2), (1, 0), (-1, 2), (-1, 1)] {
+            state.step_burst(step);
+            assert_eq!(state.burst_index, expected, "step {}", step);
+            assert_eq!(shown(&state), expected, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn short_bursts_show_their_last_frame() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = AppData::default();
+        bind_shm(&mut state, &connection, &queue_handle);
+        // a capture of output 1 failed, its burst is a frame shorter
+        let long: Vec<CapturedFrame> = (0..3).map(|_| test_frame(&state, &queue_handle)).collect();
+        let short: Vec<CapturedFrame> = (0..2).map(|_| test_frame(&state, &queue_handle)).collect();
+        let (last_long, last_short) = (long[2].buffer.clone(), short[1].buffer.clone());
+        state.burst_frames.insert(0, long);
+        state.burst_frames.insert(1, short);
+
+        // the longest burst sets the amount of frames to step through
+        state.step_burst(-1);
+        assert_eq!(state.burst_index, 2);
+        assert_eq!(state.buffers.as_ref().unwrap()[&0], last_long);
+        assert_eq!(state.buffers.as_ref().unwrap()[&1], last_short);
+
+        state.pick_burst_frame();
+        assert!(state.frame_picked);
+        assert!(state.burst_frames.is_empty());
+        assert_eq!(state.buffers.as_ref().unwrap()[&0], last_long);
+        assert_eq!(state.buffers.as_ref().unwrap()[&1], last_short);
+        // stepping after the pick doesn't do anything
+        state.step_burst(1);
+        assert_eq!(state.burst_index, 2);
+    }
+
+    #[test]
+    fn replay_ring_reuses_evicted_frames() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = AppData::default();
+        bind_shm(&mut state, &connection, &queue_handle);
+        let mut captured = Vec::new();
+        for _ in 0..4 {
+            let frame = test_frame(&state, &queue_handle);
+            captured.push(frame.buffer.clone());
+            state.restore_frame(0, frame);
+            state.captured_outputs = vec![0];
+            state.record_replay_frames(3);
+        }
+        // the oldest frame made room for the newest one
+        let ring: Vec<_> = state.replay_frames[&0]
+            .iter()
+            .map(|(_, frame)| frame.buffer.clone())
+            .collect();
+        assert_eq!(ring, captured[1..]);
+        assert_eq!(state.spare_frames[&0].len(), 1);
+
+        // without wl_shm, the next capture can only get a buffer by reusing the evicted one
+        let other = test_frame(&state, &queue_handle);
+        state.shm = None;
+        let info = other.info;
+        assert_eq!(
+            state.create_shm_buffer(0, info, &queue_handle),
+            Some(captured[0].clone())
+        );
+        assert!(state.spare_frames[&0].is_empty());
+        // spares with other parameters don't fit & are thrown away
+        state.spare_frames.get_mut(&0).unwrap().push(other);
+        let larger = ShmBufferInfo {
+            width: 2,
+            stride: 8,
+            ..info
+        };
+        assert_eq!(state.create_shm_buffer(0, larger, &queue_handle), None);
+        assert!(state.spare_frames[&0].is_empty());
+    }
+
+    #[test]
+    fn replay_selects_frame_from_before_delay() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = AppData {
+            replay: Some(500),
+            ..Default::default()
+        };
+        bind_shm(&mut state, &connection, &queue_handle);
+        let region = Region {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        let now = Instant::now();
+        let mut rings = Vec::new();
+        // a ring that goes back further than the delay, one that's younger than it & an empty one
+        for (output, ages) in [
+            (0, vec![900, 600, 400, 100]),
+            (1, vec![300, 100]),
+            (2, vec![]),
+        ] {
+            vec_insert(&mut state.regions, output, region);
+            let ring: VecDeque<(Instant, CapturedFrame)> = ages
+                .into_iter()
+                .map(|age| {
+                    let frame = test_frame(&state, &queue_handle);
+                    (now - Duration::from_millis(age), frame)
+                })
+                .collect();
+            rings.push(
+                ring.iter()
+                    .map(|(_, f)| f.buffer.clone())
+                    .collect::<Vec<_>>(),
+            );
+            state.replay_frames.insert(output, ring);
+        }
+
+        state.select_replay_frames();
+        let buffers = state.buffers.as_ref().unwrap();
+        // the newest frame that is at least as old as the delay
+        assert_eq!(buffers[&0], rings[0][1]);
+        // the oldest frame if all of them are younger than the delay
+        assert_eq!(buffers[&1], rings[1][0]);
+        // nothing was recorded of output 2, it's left alone
+        assert!(!buffers.contains_key(&2));
+        assert!(!state.regions.as_ref().unwrap().contains_key(&2));
+        let mut replayed = state.captured_outputs.clone();
+        replayed.sort();
+        assert_eq!(replayed, vec![0, 1]);
+        assert_eq!(state.frames_ready, 2);
+        assert!(state.replay_frames.is_empty());
+    }
+
+    #[test]
+    fn refreeze_of_output_that_is_not_shown() {
+        let (connection, queue, _server) = test_connection();
+        let queue_handle = queue.handle();
+        let mut state = AppData {
+            refreeze_uncover: true,
+            refreezing: true,
+            refreeze_outputs: vec![0],
+            ..Default::default()
+        };
+        bind_shm(&mut state, &connection, &queue_handle);
+        // the compositor never shows the uncovered output, e.g. because it's off
+        state
+            .uncover_deadlines
+            .insert(0, Instant::now() - Duration::from_millis(1));
+        state.capture_uncovered_outputs(&queue_handle);
+        assert!(state.uncover_deadlines.is_empty());
+        // capturing it failed & there's no previous frame, the refreeze finishes anyway
+        assert_eq!(state.captured_outputs, vec![0]);
+        state.finish_refreeze(&queue_handle);
+        assert!(!state.refreezing);
+        assert!(state.refreeze_outputs.is_empty());
+        assert_eq!(state.blank_outputs, vec![0]);
+    }
+
+    #[test]
+    fn click_that_focuses_on_demand_freeze_does_not_exit() {
+        use wl_pointer::ButtonState;
+        let click = |state: &mut AppData| {
+            state.pointer_button(wayland_client::WEnum::Value(ButtonState::Pressed));
+            state.pointer_button(wayland_client::WEnum::Value(ButtonState::Released));
+        };
+        let on_demand = || AppData {
+            keyboard_mode: KeyboardMode::OnDemand,
+            pointer_output: Some(0),
+            ..Default::default()
+        };
+
+        // the compositor focuses the surface on the press, the enter arrives before the press
+        let mut state = on_demand();
+        state.keyboard_entered();
+        click(&mut state);
+        assert!(!state.exit);
+        // the next click exits
+        click(&mut state);
+        assert!(state.exit);
+
+        // or after it, but before the release
+        let mut state = on_demand();
+        state.pointer_button(wayland_client::WEnum::Value(ButtonState::Pressed));
+        state.keyboard_entered();
+        state.pointer_button(wayland_client::WEnum::Value(ButtonState::Released));
+        assert!(!state.exit);
+        click(&mut state);
+        assert!(state.exit);
+
+        // focus that came back without a click, e.g. after a launcher on top closed
+        let mut state = on_demand();
+        state.keyboard_entered();
+        state.keyboard_entered_at = Some(Instant::now() - Duration::from_secs(5));
+        click(&mut state);
+        assert!(state.exit);
+    }
+
+    #[test]
+    fn shm_format_selection() {
+        use wl_shm::Format;
+        // 8 bits per channel formats win, argb8888 & xrgb8888 don't have to be advertised
+        assert_eq!(
+            select_shm_format(&[Format::Xrgb2101010, Format::Argb8888], &[]),
+            Some(Format::Argb8888)
+        );
+        // other formats have to be advertised by wl_shm
+        assert_eq!(select_shm_format(&[Format::Xrgb2101010], &[]), None);
+        // screencopy frames only need a format wl_shm accepts, even if it isn't a preferred one
+        assert!(shm_format_supported(Format::Bgr888, &[Format::Bgr888]));
+        assert!(shm_format_supported(Format::Xrgb8888, &[]));
+        assert!(!shm_format_supported(
+            Format::Bgr888,
+            &[Format::Xbgr2101010]
+        ));
+        assert_eq!(
+            select_shm_format(
+                &[Format::Rgb565, Format::Xbgr16161616f],
+                &[Format::Rgb565, Format::Xbgr16161616f]
+            ),
+            Some(Format::Xbgr16161616f)
+        );
+        assert_eq!(shm_format_bpp(Format::Rgb565), Some(2));
+        assert_eq!(shm_format_bpp(Format::Xrgb2101010), Some(4));
+        assert_eq!(shm_format_bpp(Format::Abgr16161616f), Some(8));
+        // every preferred format needs a known size
+        for format in SHM_FORMAT_PREFERENCE {
+            assert!(shm_format_bpp(format).is_some(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn y_inverted_buffer_matches_output() {
+        let surface = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        for output_transform in TRANSFORMS {
+            // what the compositor copies into the buffer: the transformed output, upside down
+            let mut buffer = apply(output_transform, &surface);
+            buffer.reverse();
+            assert_eq!(
+                apply(buffer_transform(output_transform, true), &surface),
+                buffer,
+                "output transform {:?}",
+                output_transform
+            );
+        }
+    }
+}